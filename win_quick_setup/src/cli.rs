@@ -0,0 +1,204 @@
+use super::commands;
+
+use commands::common::InstallActionType;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use log::LevelFilter;
+use serde_json::{json, Value};
+
+// the long flags keep the old "--install Conf.json" form working, commands saved for resuming
+// after a reboot still use it
+#[derive(Parser)]
+#[command(
+    name = "win_quick_setup",
+    version,
+    about = "Sets up a Windows machine from json configs"
+)]
+pub struct Cli {
+    /// Lowest level of log messages shown: off, error, warn, info, debug or trace.
+    #[arg(long, global = true, default_value = "info", value_name = "LEVEL")]
+    pub log_level: LevelFilter,
+
+    /// Also append the log to this file.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub log_file: Option<String>,
+
+    #[command(subcommand)]
+    pub command: CliCommand,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    /// Install everything described by the configs.
+    #[command(long_flag = "install")]
+    Install(RunArgs),
+
+    /// Uninstall everything described by the configs.
+    #[command(long_flag = "uninstall")]
+    Uninstall(RunArgs),
+
+    /// Update everything described by the configs.
+    #[command(long_flag = "update")]
+    Update(RunArgs),
+
+    /// Print the steps an action would run without changing anything.
+    Plan(PlanArgs),
+
+    /// Statically check the configs and all of their includes.
+    #[command(long_flag = "validate")]
+    Validate(ConfigArgs),
+
+    /// List the steps of the configs and all of their includes.
+    List(ListArgs),
+
+    /// Print the JSON Schema of the config format.
+    #[command(long_flag = "schema")]
+    Schema,
+}
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Config files, run one after the other.
+    #[arg(required = true, value_name = "CONFIG")]
+    pub configs: Vec<String>,
+
+    /// Set an install value, can be repeated, ex: --set PROGRAMS_DIR=D:\Tools\. true, false and
+    /// non negative integers are stored as bool and number. Steps can't change values set here.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_install_value)]
+    pub values: Vec<(String, Value)>,
+
+    /// Set the install values of a json object of strings, numbers and bools, can be repeated,
+    /// --set wins over values from files.
+    #[arg(long = "vars", value_name = "FILE")]
+    pub var_files: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct FilterArgs {
+    /// Only run steps matching one of the patterns, a pattern is a command name (winget), a config
+    /// file name (VMs.json) or a step location (General.json#3), can be repeated.
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<String>,
+
+    /// Skip steps matching one of the patterns, same format as --only, can be repeated.
+    #[arg(long, value_name = "PATTERN")]
+    pub skip: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct RunArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// Only print the steps that would run, nothing gets changed.
+    #[arg(long, alias = "plan")]
+    pub dry_run: bool,
+
+    /// Skip the steps completed by a previous interrupted run.
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Go on with the next step when one fails, failures are listed at the end.
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Most steps running a command at the same time, in parallel blocks and blocks using needs.
+    /// No limit if missing.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub jobs: Option<u32>,
+
+    /// Fail a step using a %NAME% placeholder that has no value instead of keeping it as is.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Write a JSON report of the run to this file.
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+
+    /// Write a JUnit XML report of the run to this file.
+    #[arg(long, value_name = "FILE")]
+    pub junit: Option<String>,
+
+    /// Start no program, answer them from this json array of {"match": REGEX, "exit_code": 0,
+    /// "stdout": "", "stderr": "", "not_found": false} objects, the first one whose regex is found
+    /// in the command line of the step wins. Programs no object matches exit with 0. Commands
+    /// only available on windows run anywhere. Tests a config before running it on a real
+    /// machine, ex: in CI, the chosen exit codes and output drive retries, conditions and captures.
+    #[arg(long, value_name = "SCRIPT")]
+    pub fake_processes: Option<String>,
+
+    /// Write the programs --fake-processes was asked to start to this json file, to check what a
+    /// config would run.
+    #[arg(long, value_name = "FILE", requires = "fake_processes")]
+    pub record_processes: Option<String>,
+
+    /// Read and write registry values in this json file instead of the windows registry, ex:
+    /// {"Software\\Foo": {"Name": "text", "Count": 3}}. Values can only be written to paths
+    /// already in it, like in the windows registry.
+    /// Other OSes use an empty registry kept in memory when missing.
+    #[arg(long, value_name = "FILE")]
+    pub registry: Option<String>,
+}
+
+#[derive(Args)]
+pub struct PlanArgs {
+    /// Action to plan.
+    #[arg(long, value_enum, default_value = "install")]
+    pub action: CliAction,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+}
+
+#[derive(Clone, ValueEnum)]
+pub enum CliAction {
+    Install,
+    Uninstall,
+    Update,
+}
+
+impl CliAction {
+    pub fn action(&self) -> InstallActionType {
+        match self {
+            CliAction::Install => InstallActionType::INSTALL,
+            CliAction::Uninstall => InstallActionType::UNINSTALL,
+            CliAction::Update => InstallActionType::UPDATE,
+        }
+    }
+}
+
+// same types set_var supports
+fn parse_typed_value(value: &str) -> Value {
+    if let Ok(value) = value.parse::<bool>() {
+        return json!(value);
+    }
+
+    if let Ok(value) = value.parse::<u32>() {
+        return json!(value);
+    }
+
+    return json!(value);
+}
+
+fn parse_install_value(arg: &str) -> Result<(String, Value), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.is_empty() => {
+            return Ok((key.to_string(), parse_typed_value(value)));
+        }
+        _ => {
+            return Err(format!("expected KEY=VALUE, found \"{}\"", arg));
+        }
+    }
+}
@@ -1,12 +1,23 @@
+pub mod call_command;
+pub mod capture;
 pub mod common;
+pub mod condition;
 pub mod conditional_command;
+pub mod define_command;
 pub mod delete_reg_key_command;
 pub mod dir_command;
+pub mod errors;
 pub mod exec_command;
+pub mod facts;
+pub mod foreach_command;
 pub mod get_reg_value_command;
+pub mod guards;
 pub mod include_command;
 pub mod paralel_exec_command;
+pub mod predicates;
+pub mod process_runner;
 pub mod ps1_command;
+pub mod registry_store;
 pub mod set_reg_value_command;
 pub mod set_var_command;
 pub mod vcpkg_command;
@@ -0,0 +1,163 @@
+use super::common::{
+    expand_string, substitute_placeholders, ActionFn, InstallActionType, StepLocation,
+};
+use super::define_command::{get_template, StepTemplate};
+use super::errors::InstallError;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_value, Map, Value};
+use std::collections::HashMap;
+
+use log::debug;
+
+use super::super::rendering::render_scoped;
+use super::super::validation::{validate_step_data, Validator};
+
+// templates calling each other end up this deep, a template calling itself never stops
+const MAX_NESTING: usize = 64;
+
+/// Runs the steps of a template registered with define.
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct CallCommand {
+    /// Name of the template.
+    name: String,
+
+    /// Values of the template parameters, placeholders get expanded.
+    #[serde(default)]
+    args: Map<String, Value>,
+}
+
+fn arg_text(value: &Value) -> String {
+    match value {
+        Value::String(string) => return string.clone(),
+        _ => return value.to_string(),
+    }
+}
+
+impl CallCommand {
+    fn parse_error(&self, location: &StepLocation, message: String) -> InstallError {
+        return InstallError::Parse {
+            location: location.clone(),
+            message,
+        };
+    }
+
+    // steps of the template with the arguments substituted, arguments and defaults get expanded
+    // first since their % are escaped once substituted
+    fn instantiate(&self, location: &StepLocation) -> Result<Value, InstallError> {
+        if location.path.len() > MAX_NESTING {
+            return Err(self.parse_error(
+                location,
+                format!(
+                    "Template \"{}\" nested too deeply, is it calling itself?",
+                    self.name
+                ),
+            ));
+        }
+
+        let template: StepTemplate = match get_template(&self.name) {
+            Some(template) => template,
+            None => {
+                return Err(self.parse_error(
+                    location,
+                    format!("Unknown template \"{}\", define it first", self.name),
+                ));
+            }
+        };
+
+        for arg in self.args.keys() {
+            if !template.params.contains_key(arg) {
+                return Err(self.parse_error(
+                    location,
+                    format!(
+                        "Template \"{}\" defined at {} has no parameter \"{}\"",
+                        self.name, template.location, arg
+                    ),
+                ));
+            }
+        }
+
+        let mut bindings: HashMap<String, String> = HashMap::new();
+        for (param, default) in template.params.iter() {
+            let value = match (self.args.get(param), default) {
+                (Some(value), _) => value,
+                (None, Value::Null) => {
+                    return Err(self.parse_error(
+                        location,
+                        format!(
+                            "Missing argument \"{}\" of template \"{}\" defined at {}",
+                            param, self.name, template.location
+                        ),
+                    ));
+                }
+                (None, default) => default,
+            };
+
+            let value =
+                expand_string(&arg_text(value)).map_err(|err| self.parse_error(location, err))?;
+            bindings.insert(param.clone(), value);
+        }
+
+        return Ok(substitute_placeholders(&template.run, &bindings));
+    }
+
+    pub async fn execute(
+        &self,
+        action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        let block = self.instantiate(location)?;
+
+        return render_scoped(&block, action, &location.child("run")).await;
+    }
+}
+
+pub struct CallCommandExecutor {}
+
+use async_trait::async_trait;
+
+#[async_trait]
+impl ActionFn for CallCommandExecutor {
+    async fn execute_command(
+        &self,
+        json_data: &Value,
+        action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        debug!("Attempting to execute CallCommand");
+
+        match from_value::<CallCommand>(json_data.clone()) {
+            Ok(cmd) => {
+                return cmd.execute(action, location).await;
+            }
+            Err(err) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to CallCommand, err: {}", err),
+                });
+            }
+        }
+    }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) =
+            validate_step_data::<CallCommand>(json_data, "CallCommand", location, validator)
+        {
+            match cmd.instantiate(location) {
+                Ok(block) => validator.validate_scoped_block(&block, &location.child("run")),
+                Err(err) => validator.report(err),
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<CallCommand>();
+    }
+
+    fn runs_nested_steps(&self) -> bool {
+        return true;
+    }
+}
@@ -0,0 +1,172 @@
+use super::common::{check_process_status, set_install_value, ProcessOutput, StepLocation};
+use super::errors::InstallError;
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+
+use log::debug;
+
+/// Stores what a process printed in an install value.
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct OutputCapture {
+    /// Name of the install value, referenced as %key% in later steps.
+    key: String,
+
+    /// Remove leading and trailing whitespace.
+    #[serde(default = "default_trim")]
+    trim: bool,
+
+    /// Store only the first match, or its first group when the regex has one, ex: "(\\d+\\.\\d+\\.\\d+)". The step fails when nothing matches.
+    #[serde(default)]
+    regex: Option<String>,
+}
+
+fn default_trim() -> bool {
+    return true;
+}
+
+impl OutputCapture {
+    fn check(&self) -> Option<String> {
+        if self.key.is_empty() {
+            return Some(String::from("capture key can't be empty"));
+        }
+
+        if let Some(regex) = &self.regex {
+            if let Err(err) = Regex::new(regex) {
+                return Some(format!("invalid capture regex \"{}\": {}", regex, err));
+            }
+        }
+
+        return None;
+    }
+
+    fn extract(&self, output: &str) -> Result<String, String> {
+        let mut value = output;
+
+        if let Some(regex) = &self.regex {
+            let re = match Regex::new(regex) {
+                Ok(re) => re,
+                Err(err) => return Err(format!("invalid regex \"{}\": {}", regex, err)),
+            };
+
+            match re.captures(value) {
+                Some(captures) => {
+                    value = captures
+                        .get(1)
+                        .or_else(|| captures.get(0))
+                        .map_or("", |m| m.as_str());
+                }
+                None => return Err(format!("no match for regex \"{}\"", regex)),
+            }
+        }
+
+        if self.trim {
+            value = value.trim();
+        }
+
+        return Ok(value.to_string());
+    }
+}
+
+// capture fields shared by the steps running a process, flattened into their data
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct ProcessCapture {
+    /// Store what the process prints to stdout in an install value, the output is still shown.
+    #[serde(default)]
+    capture_stdout: Option<OutputCapture>,
+
+    /// Store what the process prints to stderr in an install value, the output is still shown.
+    #[serde(default)]
+    capture_stderr: Option<OutputCapture>,
+
+    /// Store the exit code in this install value, a non zero exit code doesn't fail the step then.
+    #[serde(default)]
+    capture_exit_code: Option<String>,
+}
+
+impl ProcessCapture {
+    pub fn captures_stdout(&self) -> bool {
+        return self.capture_stdout.is_some();
+    }
+
+    pub fn captures_stderr(&self) -> bool {
+        return self.capture_stderr.is_some();
+    }
+
+    // problems found without running anything, reported by the validator
+    pub fn check(&self) -> Option<String> {
+        if let Some(message) = self
+            .capture_stdout
+            .as_ref()
+            .and_then(|capture| capture.check())
+        {
+            return Some(message);
+        }
+
+        if let Some(message) = self
+            .capture_stderr
+            .as_ref()
+            .and_then(|capture| capture.check())
+        {
+            return Some(message);
+        }
+
+        if let Some(key) = &self.capture_exit_code {
+            if key.is_empty() {
+                return Some(String::from("capture_exit_code can't be empty"));
+            }
+        }
+
+        return None;
+    }
+
+    fn store_output(
+        capture: &Option<OutputCapture>,
+        output: &str,
+        command: &str,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        if let Some(capture) = capture {
+            match capture.extract(output) {
+                Ok(value) => {
+                    debug!("Captured \"{}\" into \"{}\"", value, capture.key);
+                    set_install_value(&capture.key, value);
+                }
+                Err(message) => {
+                    return Err(InstallError::Capture {
+                        location: location.clone(),
+                        command: command.to_string(),
+                        key: capture.key.clone(),
+                        message,
+                    });
+                }
+            }
+        }
+
+        return Ok(());
+    }
+
+    // fails the step like check_process_status would, unless the exit code itself is captured,
+    // and stores the captured values
+    pub fn store(
+        &self,
+        output: &ProcessOutput,
+        command: &str,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        match (&self.capture_exit_code, output.code) {
+            (Some(key), Some(code)) => {
+                debug!("Captured exit code {} into \"{}\"", code, key);
+                set_install_value(key, code);
+            }
+            _ => check_process_status(output.code, command, location)?,
+        }
+
+        ProcessCapture::store_output(&self.capture_stdout, &output.stdout, command, location)?;
+        ProcessCapture::store_output(&self.capture_stderr, &output.stderr, command, location)?;
+
+        return Ok(());
+    }
+}
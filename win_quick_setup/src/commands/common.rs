@@ -1,14 +1,28 @@
 use lazy_static::lazy_static;
 use regex::Regex;
-use serde::de::DeserializeOwned;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use serde::de::{self, DeserializeOwned};
 use serde::Serialize;
 use serde::{Deserialize, Deserializer};
 use serde_json::{json, Value};
-use std::error::Error;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt;
 use std::option::Option;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::time::delay_for;
 
-use log::warn;
+use log::{debug, warn};
+
+use super::errors::InstallError;
+use super::facts::get_fact;
+use super::process_runner::{process_runner, ProcessSpec};
+
+use super::super::validation::Validator;
 
 // if in the feature chocolatey will no longer be installed in env:ProgramData\\chocolatey this path will need to be changed
 pub const REFRESHENV_COMMAND: &str ="Set-ExecutionPolicy Bypass -Scope Process; Import-Module $env:ProgramData\\chocolatey\\helpers\\chocolateyProfile.psm1;refreshenv;";
@@ -19,6 +33,53 @@ pub enum InstallActionType {
     UPDATE,
 }
 
+impl InstallActionType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstallActionType::INSTALL => "install",
+            InstallActionType::UNINSTALL => "uninstall",
+            InstallActionType::UPDATE => "update",
+        }
+    }
+}
+
+// identifies a step by the config file it was loaded from and its index path inside that file,
+// nested blocks add their own segments, ex: "C:\conf\VMs.json#1/run/0"
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StepLocation {
+    pub conf_file: String,
+    pub path: Vec<String>,
+}
+
+impl StepLocation {
+    pub fn new(conf_file: &str) -> StepLocation {
+        return StepLocation {
+            conf_file: conf_file.to_string(),
+            path: Vec::new(),
+        };
+    }
+
+    pub fn child<T: ToString>(&self, segment: T) -> StepLocation {
+        let mut path = self.path.clone();
+        path.push(segment.to_string());
+
+        return StepLocation {
+            conf_file: self.conf_file.clone(),
+            path,
+        };
+    }
+}
+
+impl fmt::Display for StepLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            return write!(f, "{}", self.conf_file);
+        }
+
+        return write!(f, "{}#{}", self.conf_file, self.path.join("/"));
+    }
+}
+
 use async_trait::async_trait;
 
 #[async_trait]
@@ -27,11 +88,141 @@ pub trait ActionFn {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>>;
+        location: &StepLocation,
+    ) -> Result<(), InstallError>;
+
+    // statically checks the step data and any nested steps, nothing gets executed
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator);
+
+    // json schema of the step data, used to generate the schema of the whole config format
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema;
+
+    // commands made of other steps, their outcome is reported through the nested steps
+    fn runs_nested_steps(&self) -> bool {
+        return false;
+    }
+
+    // commands that only define install values for later steps, they aren't affected by --only
+    fn sets_install_values(&self) -> bool {
+        return false;
+    }
+
+    // locks always held while the command runs, for tools breaking when started twice at once
+    fn default_locks(&self) -> Vec<String> {
+        return Vec::new();
+    }
+
+    // commands registering something for later steps that isn't journaled, they run again on
+    // resume and aren't affected by --only
+    fn is_definition(&self) -> bool {
+        return false;
+    }
 }
 
 lazy_static! {
     static ref INSTALL_VALUES: Mutex<serde_json::Value> = Mutex::new(json!({}));
+    // keys given on the command line, steps can't change them
+    static ref OVERRIDDEN_KEYS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static KEEP_GOING: AtomicBool = AtomicBool::new(false);
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+// how often running processes get checked for completion, timeout and cancellation
+pub const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::SeqCst);
+}
+
+// when set, commands only print what they would do, variables and conditions are still evaluated
+pub fn is_dry_run() -> bool {
+    return DRY_RUN.load(Ordering::SeqCst);
+}
+
+pub fn print_planned_step(description: &str) {
+    println!("[dry-run] {}", description);
+}
+
+pub fn set_keep_going(enabled: bool) {
+    KEEP_GOING.store(enabled, Ordering::SeqCst);
+}
+
+// when set, every step behaves as if it had continue_on_error
+pub fn is_keep_going() -> bool {
+    return KEEP_GOING.load(Ordering::SeqCst);
+}
+
+pub fn set_strict(enabled: bool) {
+    STRICT.store(enabled, Ordering::SeqCst);
+}
+
+// when set, a placeholder without a value fails the step instead of being kept as is
+pub fn is_strict() -> bool {
+    return STRICT.load(Ordering::SeqCst);
+}
+
+// set on Ctrl-C, running processes get killed and no further steps are started
+pub fn cancel_run() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    return CANCELLED.load(Ordering::SeqCst);
+}
+
+// returns early once the run gets cancelled
+pub async fn delay_unless_cancelled(duration: Duration) {
+    let started = Instant::now();
+    while !is_cancelled() && started.elapsed() < duration {
+        delay_for(PROCESS_POLL_INTERVAL.min(duration - started.elapsed())).await;
+    }
+}
+
+// exit code of a finished process, None when it got terminated, with what it printed when the
+// output got captured
+pub struct ProcessOutput {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ProcessOutput {
+    pub fn success(&self) -> bool {
+        return self.code == Some(0);
+    }
+}
+
+// runs the process with the current process runner and awaits it, the whole process tree gets
+// killed once timeout (in seconds) expires or the run gets cancelled
+pub async fn run_process(
+    spec: &ProcessSpec,
+    command_line: &str,
+    timeout: Option<u64>,
+    location: &StepLocation,
+) -> Result<ProcessOutput, InstallError> {
+    return process_runner()
+        .run(spec, command_line, timeout, location)
+        .await;
+}
+
+// maps the exit code of a finished process to the error reported for the step
+pub fn check_process_status(
+    code: Option<i32>,
+    command: &str,
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    if code != Some(0) {
+        return Err(InstallError::ExitCode {
+            location: location.clone(),
+            command: command.to_string(),
+            code,
+        });
+    }
+
+    return Ok(());
 }
 
 pub fn get_install_value<T: DeserializeOwned>(key: &str) -> Option<T> {
@@ -50,34 +241,229 @@ pub fn get_install_value<T: DeserializeOwned>(key: &str) -> Option<T> {
 }
 
 pub fn set_install_value<T: Serialize>(key: &str, value: T) {
+    if OVERRIDDEN_KEYS.lock().unwrap().contains(key) {
+        debug!(
+            "Install value \"{}\" was set on the command line, keeping it",
+            key
+        );
+        return;
+    }
+
     let mut install_vals = INSTALL_VALUES.lock().unwrap();
 
     install_vals[key] = json!(value);
 }
 
-pub fn expand_string(input_string: &str) -> String {
-    let re = Regex::new(r"%(.*?)%").unwrap();
+pub fn remove_install_value(key: &str) {
+    if OVERRIDDEN_KEYS.lock().unwrap().contains(key) {
+        return;
+    }
+
+    if let Value::Object(install_vals) = &mut *INSTALL_VALUES.lock().unwrap() {
+        install_vals.remove(key);
+    }
+}
+
+// value passed with --set or --vars, wins over the ones set by steps
+pub fn override_install_value(key: &str, value: &Value) {
+    OVERRIDDEN_KEYS.lock().unwrap().insert(key.to_string());
 
-    let result = re.replace_all(input_string, |caps: &regex::Captures| {
-        let captured_value = caps.get(1).map_or("", |m| m.as_str());
+    let mut install_vals = INSTALL_VALUES.lock().unwrap();
 
-        let install_val: Option<String> = get_install_value(captured_value);
-        match install_val {
-            Some(val) => val,
-            None => {
-                let install_val: Option<u32> = get_install_value(captured_value);
-                match install_val {
-                    Some(val) => val.to_string(),
-                    None => {
-                        warn!("Failed to find install value: \"{}\"", captured_value);
-                        return captured_value.to_owned().to_string();
-                    }
-                }
+    install_vals[key] = value.clone();
+}
+
+pub fn get_install_values() -> Value {
+    return INSTALL_VALUES.lock().unwrap().clone();
+}
+
+pub fn restore_install_values(values: &Value) {
+    if let Value::Object(values) = values {
+        let mut install_vals = INSTALL_VALUES.lock().unwrap();
+
+        for (key, value) in values.iter() {
+            install_vals[key] = value.clone();
+        }
+    }
+}
+
+lazy_static! {
+    // %% is a literal percent sign, names start with a letter or an underscore so batch
+    // arguments (%1), url encoding (%20) and printf formats (%d of %s) are left alone,
+    // ex: %NAME%, %NAME:-default% or %NAME:-default|trim|path_join:bin%
+    static ref PLACEHOLDER_RE: Regex = Regex::new(
+        r"%%|%([A-Za-z_][A-Za-z0-9_().\-]*)(?::-([^%|]*))?((?:\|[a-z_]+(?::[^%|]*)?)*)%"
+    )
+    .unwrap();
+}
+
+fn lookup_placeholder(name: &str) -> Option<String> {
+    match get_install_value::<Value>(name) {
+        Some(Value::String(val)) => return Some(val),
+        Some(Value::Number(val)) => return Some(val.to_string()),
+        Some(Value::Bool(val)) => return Some(val.to_string()),
+        // lists expand to json, foreach parses them back
+        Some(Value::Array(val)) => return Some(Value::Array(val).to_string()),
+        _ => {
+            if let Some(val) = get_fact(name) {
+                return Some(val);
             }
+
+            return env::var(name).ok();
+        }
+    }
+}
+
+// filter is the text after "|", with its argument after ":" if it takes one
+fn apply_placeholder_filter(value: String, filter: &str) -> Result<String, String> {
+    let (name, arg) = match filter.split_once(':') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (filter, None),
+    };
+
+    match (name, arg) {
+        ("upper", None) => return Ok(value.to_uppercase()),
+        ("lower", None) => return Ok(value.to_lowercase()),
+        ("trim", None) => return Ok(value.trim().to_string()),
+        // a single command line argument, for values with spaces like paths
+        ("quote", None) => return Ok(format!("\"{}\"", value.replace('"', "\\\""))),
+        ("path_join", Some(arg)) => {
+            return Ok(Path::new(&value).join(arg).to_string_lossy().to_string());
+        }
+        ("path_join", None) => {
+            return Err(String::from("path_join needs a path, ex: path_join:bin"))
+        }
+        _ => return Err(format!("Unknown placeholder filter: \"{}\"", filter)),
+    }
+}
+
+fn expand_placeholder(caps: &regex::Captures, missing: &mut Vec<String>) -> Result<String, String> {
+    let name = match caps.get(1) {
+        Some(name) => name.as_str(),
+        None => return Ok(String::from("%")),
+    };
+
+    match (lookup_placeholder(name), caps.get(2)) {
+        (Some(val), _) => return apply_placeholder_filters(caps, val),
+        (None, Some(default)) => {
+            return apply_placeholder_filters(caps, default.as_str().to_string())
+        }
+        (None, None) => {
+            missing.push(name.to_string());
+            return Ok(caps[0].to_string());
+        }
+    }
+}
+
+fn apply_placeholder_filters(caps: &regex::Captures, value: String) -> Result<String, String> {
+    let mut value = value;
+
+    // first entry is the empty text before the first "|"
+    for filter in caps[3].split('|').skip(1) {
+        value = apply_placeholder_filter(value, filter)?;
+    }
+
+    return Ok(value);
+}
+
+// replaces only the placeholders named in bindings, everything else is left for expand_string
+// once the steps run, substituted values get their % escaped so they aren't expanded again
+pub fn substitute_placeholders(value: &Value, bindings: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(string) => {
+            let result = PLACEHOLDER_RE.replace_all(string, |caps: &regex::Captures| {
+                let bound = caps.get(1).and_then(|name| bindings.get(name.as_str()));
+                match bound.map(|bound| apply_placeholder_filters(caps, bound.clone())) {
+                    Some(Ok(substituted)) => return substituted.replace('%', "%%"),
+                    // unknown filters get reported when the step runs
+                    _ => return caps[0].to_string(),
+                }
+            });
+            return Value::String(result.to_string());
+        }
+        Value::Array(array) => {
+            return Value::Array(
+                array
+                    .iter()
+                    .map(|value| substitute_placeholders(value, bindings))
+                    .collect(),
+            );
+        }
+        Value::Object(object) => {
+            return Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), substitute_placeholders(value, bindings)))
+                    .collect(),
+            );
         }
-    });
+        _ => return value.clone(),
+    }
+}
+
+// unresolved placeholders are kept as they are and their names pushed to missing
+fn expand_placeholders(input_string: &str, missing: &mut Vec<String>) -> Result<String, String> {
+    let mut error: Option<String> = None;
+
+    let result =
+        PLACEHOLDER_RE.replace_all(
+            input_string,
+            |caps: &regex::Captures| match expand_placeholder(caps, missing) {
+                Ok(value) => return value,
+                Err(err) => {
+                    error.get_or_insert(err);
+                    return caps[0].to_string();
+                }
+            },
+        );
+
+    match error {
+        Some(err) => return Err(format!("{} in \"{}\"", err, input_string)),
+        None => return Ok(result.to_string()),
+    }
+}
 
-    return result.to_string();
+// %NAME% is looked up in the install values, then the built-in facts (see facts.rs) and last
+// the environment variables of the process, missing values without a default are an error in
+// strict mode
+pub fn expand_string(input_string: &str) -> Result<String, String> {
+    let mut missing: Vec<String> = Vec::new();
+    let result = expand_placeholders(input_string, &mut missing)?;
+
+    if !missing.is_empty() && is_strict() {
+        return Err(format!(
+            "Failed to find install value: \"{}\" in \"{}\"",
+            missing.join("\", \""),
+            input_string
+        ));
+    }
+
+    for name in missing {
+        warn!("Failed to find install value: \"{}\"", name);
+    }
+
+    return Ok(result);
+}
+
+// every string inside the value expanded the way commands see them, missing values are already
+// reported while running the command
+pub fn expand_value(value: &Value) -> Value {
+    match value {
+        Value::String(string) => match expand_placeholders(string, &mut Vec::new()) {
+            Ok(expanded) => return Value::String(expanded),
+            Err(_) => return value.clone(),
+        },
+        Value::Array(array) => return Value::Array(array.iter().map(expand_value).collect()),
+        Value::Object(object) => {
+            return Value::Object(
+                object
+                    .iter()
+                    .map(|(key, value)| (key.clone(), expand_value(value)))
+                    .collect(),
+            );
+        }
+        _ => return value.clone(),
+    }
 }
 
 pub fn expand_string_deserializer<'de, D>(deserializer: D) -> Result<String, D::Error>
@@ -86,5 +472,23 @@ where
 {
     let raw_value: String = Deserialize::deserialize(deserializer)?;
 
-    Ok(expand_string(&raw_value.as_str()))
+    return expand_string(&raw_value.as_str()).map_err(de::Error::custom);
+}
+
+pub fn expand_optional_string_deserializer<'de, D>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw_value: Option<String> = Deserialize::deserialize(deserializer)?;
+
+    match raw_value {
+        Some(raw_value) => {
+            return expand_string(&raw_value.as_str())
+                .map(Some)
+                .map_err(de::Error::custom);
+        }
+        None => return Ok(None),
+    }
 }
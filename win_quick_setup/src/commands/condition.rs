@@ -0,0 +1,734 @@
+use super::common::expand_string;
+use super::predicates::{check_predicate, evaluate_predicate};
+
+use futures::future::{BoxFuture, FutureExt};
+
+use std::cmp::Ordering;
+use std::fmt;
+
+// conditions of if steps, ex: %RUN_KEY:-none% != %CMD% && (%OS% == windows || !%IS_ADMIN%)
+//
+// operands are bare words or quoted strings, consecutive bare words form a single operand so
+// "Windows 10 == %OS_NAME%" keeps working, placeholders are expanded after parsing so values
+// containing quotes or operators can't change the meaning of the condition
+//
+// operands made of digits with at most one dot are compared as numbers, ones with more dots or a
+// v, pre-release or build part (1.2.3, v2.0-beta) as semantic versions along with the number they
+// are compared to, and anything else as strings, so 0.5 > 0.25, 1.10.0 > 1.9.0, 1.2 == 1.2.0 and
+// inf != infinity, an operand on its own is true unless it's empty, 0, false or no
+//
+// functions like exists(path) or package_installed(winget, Git.Git) check the machine, see
+// predicates.rs, commas only separate their arguments and are part of the value anywhere else
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+}
+
+// text of a value before placeholders get expanded
+pub struct Operand {
+    text: String,
+    quoted: bool,
+}
+
+pub enum Condition {
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    Compare(Operand, CompareOp, Operand),
+    Value(Operand),
+    Call(String, Vec<Operand>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    // bare word, with the whitespace in front of it when it directly follows another word
+    Word(String, String),
+    // same as words, only a value separator inside function calls
+    Comma(String),
+    // function name, the token includes the opening parenthesis
+    Call(String),
+    Quoted(String),
+    Op(CompareOp),
+    And,
+    Or,
+    Not,
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word, _) => write!(f, "\"{}\"", word),
+            Token::Comma(_) => write!(f, ","),
+            Token::Call(name) => write!(f, "{}(", name),
+            Token::Quoted(value) => write!(f, "\"{}\"", value),
+            Token::Op(op) => write!(f, "{}", op.symbol()),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+impl CompareOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            CompareOp::Eq => "==",
+            CompareOp::Ne => "!=",
+            CompareOp::Lt => "<",
+            CompareOp::Le => "<=",
+            CompareOp::Gt => ">",
+            CompareOp::Ge => ">=",
+            CompareOp::Contains => "contains",
+            CompareOp::NotContains => "!contains",
+        }
+    }
+}
+
+impl Operand {
+    // bare words get trimmed since values read from the registry or commands often end with
+    // whitespace, quoted ones are taken as they are
+    fn value(&self) -> Result<String, String> {
+        let value = expand_string(&self.text)?;
+        if self.quoted {
+            return Ok(value);
+        }
+
+        return Ok(value.trim().to_string());
+    }
+}
+
+fn is_word_end(c: char) -> bool {
+    return c.is_whitespace() || "(),\"'=<>!&|".contains(c);
+}
+
+// whitespace between the token starting at start and a previous word or comma
+fn space_before(chars: &[char], start: usize, tokens: &[Token]) -> String {
+    match tokens.last() {
+        Some(Token::Word(..)) | Some(Token::Comma(_)) => {
+            let mut space_start = start;
+            while space_start > 0 && chars[space_start - 1].is_whitespace() {
+                space_start -= 1;
+            }
+            return chars[space_start..start].iter().collect();
+        }
+        _ => return String::new(),
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let simple_token = match (c, next) {
+            ('(', _) => Some((Token::LeftParen, 1)),
+            (')', _) => Some((Token::RightParen, 1)),
+            ('&', Some('&')) => Some((Token::And, 2)),
+            ('|', Some('|')) => Some((Token::Or, 2)),
+            ('=', Some('=')) => Some((Token::Op(CompareOp::Eq), 2)),
+            ('!', Some('=')) => Some((Token::Op(CompareOp::Ne), 2)),
+            ('<', Some('=')) => Some((Token::Op(CompareOp::Le), 2)),
+            ('>', Some('=')) => Some((Token::Op(CompareOp::Ge), 2)),
+            ('<', _) => Some((Token::Op(CompareOp::Lt), 1)),
+            ('>', _) => Some((Token::Op(CompareOp::Gt), 1)),
+            _ => None,
+        };
+
+        if let Some((token, length)) = simple_token {
+            tokens.push(token);
+            i += length;
+            continue;
+        }
+
+        match c {
+            ',' => {
+                tokens.push(Token::Comma(space_before(&chars, i, &tokens)));
+                i += 1;
+                continue;
+            }
+            '!' => {
+                let rest: String = chars[i + 1..].iter().take(9).collect();
+                if rest.starts_with("contains") && rest.chars().nth(8).is_none_or(is_word_end) {
+                    tokens.push(Token::Op(CompareOp::NotContains));
+                    i += 9;
+                } else {
+                    tokens.push(Token::Not);
+                    i += 1;
+                }
+                continue;
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut closed = false;
+                i += 1;
+                while i < chars.len() {
+                    match (chars[i], chars.get(i + 1).copied()) {
+                        ('\\', Some(escaped)) if escaped == c || escaped == '\\' => {
+                            value.push(escaped);
+                            i += 2;
+                        }
+                        (quote, _) if quote == c => {
+                            closed = true;
+                            i += 1;
+                            break;
+                        }
+                        (other, _) => {
+                            value.push(other);
+                            i += 1;
+                        }
+                    }
+                }
+
+                if !closed {
+                    return Err(format!("Missing closing {} in condition", c));
+                }
+
+                tokens.push(Token::Quoted(value));
+                continue;
+            }
+            '=' | '&' | '|' => {
+                return Err(format!("Unexpected \"{}\" in condition", c));
+            }
+            _ => {
+                let start = i;
+                while i < chars.len() && !is_word_end(chars[i]) {
+                    // placeholders may contain any character, ex: %NAME:-C:\Tools|path_join:bin%
+                    if chars[i] == '%' {
+                        if let Some(end) = chars[i + 1..].iter().position(|c| *c == '%') {
+                            i += end + 1;
+                        }
+                    }
+                    i += 1;
+                }
+
+                let word: String = chars[start..i].iter().collect();
+                if word == "contains" {
+                    tokens.push(Token::Op(CompareOp::Contains));
+                } else if chars.get(i) == Some(&'(')
+                    && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    tokens.push(Token::Call(word));
+                    i += 1;
+                } else {
+                    tokens.push(Token::Word(word, space_before(&chars, start, &tokens)));
+                }
+                continue;
+            }
+        }
+    }
+
+    return Ok(tokens);
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    // parsing the arguments of a function, where commas separate values
+    in_call: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.position);
+    }
+
+    fn parse_or(&mut self) -> Result<Condition, String> {
+        let mut condition = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            condition = Condition::Or(Box::new(condition), Box::new(self.parse_and()?));
+        }
+
+        return Ok(condition);
+    }
+
+    fn parse_and(&mut self) -> Result<Condition, String> {
+        let mut condition = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            condition = Condition::And(Box::new(condition), Box::new(self.parse_unary()?));
+        }
+
+        return Ok(condition);
+    }
+
+    fn parse_unary(&mut self) -> Result<Condition, String> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.position += 1;
+                return Ok(Condition::Not(Box::new(self.parse_unary()?)));
+            }
+            Some(Token::LeftParen) => {
+                self.position += 1;
+                let condition = self.parse_or()?;
+                if self.peek() != Some(&Token::RightParen) {
+                    return Err(String::from("Missing closing ) in condition"));
+                }
+                self.position += 1;
+                return Ok(condition);
+            }
+            Some(Token::Call(name)) => {
+                let name = name.clone();
+                self.position += 1;
+                return self.parse_call(name);
+            }
+            _ => return self.parse_comparison(),
+        }
+    }
+
+    fn parse_call(&mut self, name: String) -> Result<Condition, String> {
+        let mut args: Vec<Operand> = Vec::new();
+
+        if self.peek() == Some(&Token::RightParen) {
+            self.position += 1;
+        } else {
+            self.in_call = true;
+            loop {
+                args.push(self.parse_operand()?);
+                match self.peek() {
+                    Some(Token::Comma(_)) => self.position += 1,
+                    Some(Token::RightParen) => {
+                        self.position += 1;
+                        break;
+                    }
+                    _ => {
+                        return Err(format!(
+                            "Missing closing ) after the arguments of {}()",
+                            name
+                        ))
+                    }
+                }
+            }
+            self.in_call = false;
+        }
+
+        check_predicate(&name, args.len())?;
+        return Ok(Condition::Call(name, args));
+    }
+
+    fn parse_comparison(&mut self) -> Result<Condition, String> {
+        let left = self.parse_operand()?;
+
+        if let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            self.position += 1;
+            let right = self.parse_operand()?;
+            return Ok(Condition::Compare(left, op, right));
+        }
+
+        return Ok(Condition::Value(left));
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        match self.peek() {
+            Some(Token::Quoted(value)) => {
+                let text = value.clone();
+                self.position += 1;
+                return Ok(Operand { text, quoted: true });
+            }
+            Some(Token::Word(..)) => {
+                let mut operand = String::new();
+                loop {
+                    match self.peek() {
+                        Some(Token::Word(word, space)) => {
+                            operand.push_str(space);
+                            operand.push_str(word);
+                        }
+                        Some(Token::Comma(space)) if !self.in_call => {
+                            operand.push_str(space);
+                            operand.push(',');
+                        }
+                        _ => break,
+                    }
+                    self.position += 1;
+                }
+                return Ok(Operand {
+                    text: operand,
+                    quoted: false,
+                });
+            }
+            Some(token) => return Err(format!("Expected a value in condition, found {}", token)),
+            None => return Err(String::from("Expected a value at the end of the condition")),
+        }
+    }
+}
+
+pub fn parse_condition(text: &str) -> Result<Condition, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+        in_call: false,
+    };
+
+    let condition = parser.parse_or()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("Unexpected {} in condition", token));
+    }
+
+    return Ok(condition);
+}
+
+// numeric parts of a semantic version and its pre-release identifiers, build metadata is ignored
+fn parse_version(text: &str) -> Option<(Vec<String>, Vec<String>)> {
+    let text = text.strip_prefix('v').unwrap_or(text);
+    let text = match text.split_once('+') {
+        Some((version, _)) => version,
+        None => text,
+    };
+    let (release, pre_release) = match text.split_once('-') {
+        Some((release, pre_release)) => {
+            (release, pre_release.split('.').map(String::from).collect())
+        }
+        None => (text, Vec::new()),
+    };
+
+    if !release.contains('.') || !release.split('.').all(is_number) {
+        return None;
+    }
+
+    return Some((release.split('.').map(String::from).collect(), pre_release));
+}
+
+// plain decimal digits, no sign, exponent or fraction
+fn is_number(text: &str) -> bool {
+    return !text.is_empty() && text.bytes().all(|c| c.is_ascii_digit());
+}
+
+// numbers of any length, leading zeros don't count
+fn compare_numbers(left: &str, right: &str) -> Ordering {
+    let left = left.trim_start_matches('0');
+    let right = right.trim_start_matches('0');
+    return left.len().cmp(&right.len()).then_with(|| left.cmp(right));
+}
+
+// integer and fraction digits of a number, ex: 10 or 0.5
+fn parse_decimal(text: &str) -> Option<(&str, &str)> {
+    match text.split_once('.') {
+        Some((integer, fraction)) if is_number(integer) && is_number(fraction) => {
+            return Some((integer, fraction));
+        }
+        Some(_) => return None,
+        None if is_number(text) => return Some((text, "")),
+        None => return None,
+    }
+}
+
+// trailing zeros of the fraction don't count, 0.50 == 0.5
+fn compare_decimals(left: (&str, &str), right: (&str, &str)) -> Ordering {
+    let left_fraction = left.1.trim_end_matches('0');
+    let right_fraction = right.1.trim_end_matches('0');
+    return compare_numbers(left.0, right.0).then_with(|| left_fraction.cmp(right_fraction));
+}
+
+fn compare_pre_release(left: &[String], right: &[String]) -> Ordering {
+    // a release is newer than any of its pre-releases
+    match (left.is_empty(), right.is_empty()) {
+        (true, true) => return Ordering::Equal,
+        (true, false) => return Ordering::Greater,
+        (false, true) => return Ordering::Less,
+        _ => {}
+    }
+
+    for (left, right) in left.iter().zip(right.iter()) {
+        let ordering = match (is_number(left), is_number(right)) {
+            (true, true) => compare_numbers(left, right),
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => left.cmp(right),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    return left.len().cmp(&right.len());
+}
+
+fn compare_values(left: &str, right: &str) -> Ordering {
+    if let (Some(left), Some(right)) = (parse_decimal(left), parse_decimal(right)) {
+        return compare_decimals(left, right);
+    }
+
+    if let (Some(left), Some(right)) = (parse_version(left), parse_version(right)) {
+        // missing parts count as 0, 1.2 == 1.2.0
+        let length = left.0.len().max(right.0.len());
+        for i in 0..length {
+            let ordering = compare_numbers(
+                left.0.get(i).map_or("0", |part| part.as_str()),
+                right.0.get(i).map_or("0", |part| part.as_str()),
+            );
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        return compare_pre_release(&left.1, &right.1);
+    }
+
+    return left.cmp(right);
+}
+
+fn is_truthy(value: &str) -> bool {
+    let value = value.trim().to_lowercase();
+    return !matches!(value.as_str(), "" | "0" | "false" | "no");
+}
+
+impl Condition {
+    // boxed since conditions nest, functions may run processes
+    pub fn evaluate(&self) -> BoxFuture<'_, Result<bool, String>> {
+        return async move {
+            match self {
+                Condition::Not(condition) => return Ok(!condition.evaluate().await?),
+                Condition::And(left, right) => {
+                    return Ok(left.evaluate().await? && right.evaluate().await?);
+                }
+                Condition::Or(left, right) => {
+                    return Ok(left.evaluate().await? || right.evaluate().await?);
+                }
+                Condition::Value(value) => return Ok(is_truthy(&value.value()?)),
+                Condition::Call(name, args) => {
+                    let mut values: Vec<String> = Vec::new();
+                    for arg in args.iter() {
+                        values.push(arg.value()?);
+                    }
+                    return evaluate_predicate(name, &values).await;
+                }
+                Condition::Compare(left, op, right) => {
+                    let left = left.value()?;
+                    let right = right.value()?;
+                    let ordering = compare_values(&left, &right);
+
+                    match op {
+                        CompareOp::Eq => return Ok(ordering == Ordering::Equal),
+                        CompareOp::Ne => return Ok(ordering != Ordering::Equal),
+                        CompareOp::Lt => return Ok(ordering == Ordering::Less),
+                        CompareOp::Le => return Ok(ordering != Ordering::Greater),
+                        CompareOp::Gt => return Ok(ordering == Ordering::Greater),
+                        CompareOp::Ge => return Ok(ordering != Ordering::Less),
+                        CompareOp::Contains => return Ok(left.contains(&right)),
+                        CompareOp::NotContains => return Ok(!left.contains(&right)),
+                    }
+                }
+            }
+        }
+        .boxed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str, space: &str) -> Token {
+        return Token::Word(word.to_string(), space.to_string());
+    }
+
+    // tree of a parsed condition, ex: (a && b) || c
+    fn describe(condition: &Condition) -> String {
+        match condition {
+            Condition::Not(condition) => return format!("!{}", describe(condition)),
+            Condition::And(left, right) => {
+                return format!("({} && {})", describe(left), describe(right));
+            }
+            Condition::Or(left, right) => {
+                return format!("({} || {})", describe(left), describe(right));
+            }
+            Condition::Compare(left, op, right) => {
+                return format!("[{} {} {}]", left.text, op.symbol(), right.text);
+            }
+            Condition::Value(value) => return value.text.clone(),
+            Condition::Call(name, args) => {
+                let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+                return format!("{}({})", name, args.join(", "));
+            }
+        }
+    }
+
+    fn parsed(text: &str) -> String {
+        return describe(&parse_condition(text).unwrap());
+    }
+
+    fn evaluate(text: &str) -> bool {
+        let condition = parse_condition(text).unwrap();
+        return futures::executor::block_on(condition.evaluate()).unwrap();
+    }
+
+    #[test]
+    fn tokenizes_operators_and_words() {
+        assert_eq!(
+            tokenize("a==b && !(c != 'd e') || f <= g").unwrap(),
+            vec![
+                word("a", ""),
+                Token::Op(CompareOp::Eq),
+                word("b", ""),
+                Token::And,
+                Token::Not,
+                Token::LeftParen,
+                word("c", ""),
+                Token::Op(CompareOp::Ne),
+                Token::Quoted(String::from("d e")),
+                Token::RightParen,
+                Token::Or,
+                word("f", ""),
+                Token::Op(CompareOp::Le),
+                word("g", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_multi_word_values_and_placeholders() {
+        assert_eq!(
+            tokenize("Windows  10 == %NAME:-C:\\Tools|path_join:bin%").unwrap(),
+            vec![
+                word("Windows", ""),
+                word("10", "  "),
+                Token::Op(CompareOp::Eq),
+                word("%NAME:-C:\\Tools|path_join:bin%", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenizes_contains_and_calls() {
+        assert_eq!(
+            tokenize("%PATH% !contains git && exists(a, b)").unwrap(),
+            vec![
+                word("%PATH%", ""),
+                Token::Op(CompareOp::NotContains),
+                word("git", ""),
+                Token::And,
+                Token::Call(String::from("exists")),
+                word("a", ""),
+                Token::Comma(String::new()),
+                word("b", " "),
+                Token::RightParen,
+            ]
+        );
+        assert_eq!(
+            tokenize("!containsx").unwrap(),
+            vec![Token::Not, word("containsx", "")]
+        );
+    }
+
+    #[test]
+    fn tokenizes_escaped_quotes() {
+        assert_eq!(
+            tokenize(r#""a \"b\" \\" 'c'"#).unwrap(),
+            vec![
+                Token::Quoted(String::from("a \"b\" \\")),
+                Token::Quoted(String::from("c")),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        assert!(tokenize("a = b").is_err());
+        assert!(tokenize("a & b").is_err());
+        assert!(tokenize("\"a").is_err());
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parsed("a || b && c"), "(a || (b && c))");
+        assert_eq!(parsed("a && b || c"), "((a && b) || c)");
+        assert_eq!(parsed("a || b || c"), "((a || b) || c)");
+    }
+
+    #[test]
+    fn not_and_parentheses() {
+        assert_eq!(parsed("!a && b"), "(!a && b)");
+        assert_eq!(parsed("!(a && b)"), "!(a && b)");
+        assert_eq!(parsed("(a || b) && c == d"), "((a || b) && [c == d])");
+        assert_eq!(parsed("!!a"), "!!a");
+    }
+
+    #[test]
+    fn comparisons_bind_tighter_than_and() {
+        assert_eq!(
+            parsed("%A% != 1.2 && x contains y"),
+            "([%A% != 1.2] && [x contains y])"
+        );
+        assert_eq!(parsed("Windows 10 == %OS%"), "[Windows 10 == %OS%]");
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        assert!(parse_condition("(a && b").is_err());
+        assert!(parse_condition("a &&").is_err());
+        assert!(parse_condition("a == b)").is_err());
+        assert!(parse_condition("== b").is_err());
+    }
+
+    #[test]
+    fn evaluates_precedence() {
+        assert!(evaluate("true || false && false"));
+        assert!(!evaluate("(true || false) && false"));
+        assert!(evaluate("!false && !0"));
+        assert!(!evaluate("!(yes || no)"));
+    }
+
+    #[test]
+    fn numbers_are_digits_only() {
+        assert!(evaluate("10 > 9"));
+        assert!(evaluate("007 == 7"));
+        assert!(evaluate("18446744073709551616 > 18446744073709551615"));
+        assert!(!evaluate("inf == infinity"));
+        assert!(!evaluate("NaN == nan"));
+        assert!(!evaluate("1e3 == 1000"));
+        assert!(!evaluate("+5 == 5"));
+    }
+
+    #[test]
+    fn single_dot_values_compare_as_numbers() {
+        assert!(evaluate("0.5 > 0.25"));
+        assert!(evaluate("1.10 < 1.9"));
+        assert!(evaluate("0.50 == 0.5"));
+        assert!(evaluate("2 > 1.99"));
+        assert!(evaluate("007.5 == 7.5"));
+        assert!(!evaluate("1. == 1"));
+        assert!(!evaluate(".5 == 0.5"));
+    }
+
+    #[test]
+    fn dotted_values_compare_as_versions() {
+        assert!(evaluate("1.10.0 > 1.9.0"));
+        assert!(evaluate("v1.10 > v1.9"));
+        assert!(evaluate("1.2 == 1.2.0"));
+        assert!(evaluate("2.40.1 >= 2.10"));
+        assert!(evaluate("v2.0.1 >= 2.0"));
+        assert!(evaluate("1.0.0-beta < 1.0.0"));
+        assert!(evaluate("1.0.0-alpha.2 < 1.0.0-alpha.10"));
+        assert!(evaluate("1.0.0+build5 == 1.0.0"));
+        assert!(evaluate("1.-1 != 1.1"));
+    }
+
+    #[test]
+    fn other_values_compare_as_strings() {
+        assert!(evaluate("abc < abd"));
+        assert!(evaluate("'a b' == \"a b\""));
+        assert!(evaluate("' a' != a"));
+        assert!(evaluate("abc contains b"));
+    }
+}
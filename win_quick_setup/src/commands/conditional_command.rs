@@ -1,26 +1,34 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
-
-use std::error::Error;
+use super::common::{ActionFn, InstallActionType, StepLocation};
+use super::condition::parse_condition;
+use super::errors::InstallError;
 
 use log::debug;
 
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, json, Value};
 
-use regex::Regex;
-
 use super::super::rendering::render;
+use super::super::schema::steps_schema;
+use super::super::validation::{validate_step_data, Validator};
 
-#[derive(Deserialize, Serialize)]
+/// Runs one of two blocks of steps depending on a condition.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct ConditionalCommand {
-    #[serde(deserialize_with = "expand_string_deserializer")]
+    /// Comparisons of the form "<value> <op> <value>", op being one of ==, !=, <, <=, >, >=, contains, !contains, combined with &&, || and ! and grouped with parentheses. Values are words or quoted strings, values made of digits with at most one dot compare as numbers and ones with more dots as versions, ex: 0.5 > 0.25 and 1.10.0 > 1.9.0. Functions: exists(path), is_dir(path), which(command), env_set(NAME), var_set(NAME), reg_exists(reg_path[, key_name]) and package_installed(winget|vcpkg, id).
     condition: String,
 
+    /// Steps executed when the condition holds.
     #[serde(default = "default_run")]
+    #[schemars(schema_with = "steps_schema")]
     run: Value,
 
+    /// Steps executed when the condition doesn't hold.
     #[serde(rename = "else")]
     #[serde(default = "default_except_run")]
+    #[schemars(schema_with = "steps_schema")]
     except: Value,
 }
 
@@ -33,94 +41,39 @@ fn default_except_run() -> Value {
 }
 
 impl ConditionalCommand {
+    async fn check_condition(&self, location: &StepLocation) -> Result<bool, InstallError> {
+        let parsed = match parse_condition(&self.condition) {
+            Ok(parsed) => parsed,
+            Err(message) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Invalid if condition \"{}\": {}", self.condition, message),
+                });
+            }
+        };
+
+        match parsed.evaluate().await {
+            Ok(result) => return Ok(result),
+            Err(message) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message,
+                });
+            }
+        }
+    }
+
     pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Checking condition: {}", self.condition);
 
-        let pattern = r"(.+)\s*(==|>=|<=|!=|<|>|contains|!contains)\s*(.+)";
-        let re = Regex::new(pattern).unwrap();
-
-        if let Some(captures) = re.captures(&self.condition) {
-            let value1 = captures.get(1).unwrap().as_str();
-            let operator = captures.get(2).unwrap().as_str();
-            let value2 = captures.get(3).unwrap().as_str();
-
-            let value1 = value1.trim_start().trim_end();
-            let value2 = value2.trim_start().trim_end();
-
-            match operator {
-                "==" => {
-                    if value1 == value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                ">=" => {
-                    if value1 >= value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                "<=" => {
-                    if value1 <= value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                ">" => {
-                    if value1 > value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                "<" => {
-                    if value1 < value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                "!=" => {
-                    if value1 != value2 {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                "contains" => {
-                    if let Some(_) = value1.find(value2) {
-                        return render(&self.run, &action).await;
-                    } else {
-                        return render(&self.except, &action).await;
-                    }
-                }
-                "!contains" => {
-                    if let Some(_) = value1.find(value2) {
-                        return render(&self.except, &action).await;
-                    } else {
-                        return render(&self.run, &action).await;
-                    }
-                }
-                _ => {
-                    return Err(format!(
-                        "Internal error, {} not a valid comparison operator",
-                        operator
-                    )
-                    .into());
-                }
-            }
+        if self.check_condition(location).await? {
+            return render(&self.run, &action, &location.child("run")).await;
         } else {
-            return Err(format!(
-                "Invalid if statement, condition \"{}\" doesn't match pattern \"{}\"",
-                self.condition, pattern
-            )
-            .into());
+            return render(&self.except, &action, &location.child("else")).await;
         }
     }
 }
@@ -135,18 +88,48 @@ impl ActionFn for ConditionalCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute ConditionalCommand");
 
         match from_value::<ConditionalCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action).await;
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(
-                    format!("Failed to convert data to ConditionalCommand, err: {}", err).into(),
-                );
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to ConditionalCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) = validate_step_data::<ConditionalCommand>(
+            json_data,
+            "ConditionalCommand",
+            location,
+            validator,
+        ) {
+            // only the syntax, values are known once the steps before it ran
+            if let Err(message) = parse_condition(&cmd.condition) {
+                validator.report(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Invalid if condition \"{}\": {}", cmd.condition, message),
+                });
+            }
+
+            validator.validate_block(&cmd.run, &location.child("run"));
+            validator.validate_block(&cmd.except, &location.child("else"));
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<ConditionalCommand>();
+    }
+
+    fn runs_nested_steps(&self) -> bool {
+        return true;
+    }
 }
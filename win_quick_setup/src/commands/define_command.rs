@@ -0,0 +1,122 @@
+use super::common::{ActionFn, InstallActionType, StepLocation};
+use super::errors::InstallError;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_value, Map, Value};
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use log::debug;
+
+use super::super::schema::steps_schema;
+use super::super::validation::{validate_step_data, Validator};
+
+// block of steps registered by define and instantiated by call
+#[derive(Clone)]
+pub struct StepTemplate {
+    // parameter names with their default, null for required ones
+    pub params: Map<String, Value>,
+    pub run: Value,
+    // where the template got defined, for error messages
+    pub location: StepLocation,
+}
+
+lazy_static! {
+    static ref TEMPLATES: Mutex<HashMap<String, StepTemplate>> = Mutex::new(HashMap::new());
+}
+
+pub fn get_template(name: &str) -> Option<StepTemplate> {
+    return TEMPLATES.lock().unwrap().get(name).cloned();
+}
+
+/// Registers a named block of steps, run with call.
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct DefineCommand {
+    /// Name used by call steps, templates are shared by all configs of the run.
+    name: String,
+
+    /// Parameters referenced as %NAME% inside run, with their default value or null when call has to pass them.
+    #[serde(default)]
+    params: Map<String, Value>,
+
+    /// Steps run by every call.
+    #[schemars(schema_with = "steps_schema")]
+    run: Value,
+}
+
+impl DefineCommand {
+    pub fn execute(&self, location: &StepLocation) -> Result<(), InstallError> {
+        if self.name.is_empty() {
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: String::from("Template name can't be empty"),
+            });
+        }
+
+        debug!("Defining template \"{}\"", self.name);
+
+        TEMPLATES.lock().unwrap().insert(
+            self.name.clone(),
+            StepTemplate {
+                params: self.params.clone(),
+                run: self.run.clone(),
+                location: location.clone(),
+            },
+        );
+
+        return Ok(());
+    }
+}
+
+pub struct DefineCommandExecutor {}
+
+use async_trait::async_trait;
+
+#[async_trait]
+impl ActionFn for DefineCommandExecutor {
+    async fn execute_command(
+        &self,
+        json_data: &Value,
+        _action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        debug!("Attempting to execute DefineCommand");
+
+        match from_value::<DefineCommand>(json_data.clone()) {
+            Ok(cmd) => {
+                return cmd.execute(location);
+            }
+            Err(err) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to DefineCommand, err: {}", err),
+                });
+            }
+        }
+    }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        // templates are registered so later call steps can be checked, their steps get
+        // validated with the arguments of each call
+        if let Some(cmd) =
+            validate_step_data::<DefineCommand>(json_data, "DefineCommand", location, validator)
+        {
+            if let Err(err) = cmd.execute(location) {
+                validator.report(err);
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<DefineCommand>();
+    }
+
+    fn is_definition(&self) -> bool {
+        return true;
+    }
+}
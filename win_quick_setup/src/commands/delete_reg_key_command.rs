@@ -1,20 +1,29 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
+use super::common::{
+    expand_string_deserializer, is_dry_run, print_planned_step, ActionFn, InstallActionType,
+    StepLocation,
+};
+use super::errors::InstallError;
+use super::registry_store::registry_store;
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
 use std::io;
 
 use log::debug;
 
-use winreg::enums::{HKEY_CURRENT_USER, KEY_ALL_ACCESS};
-use winreg::RegKey;
-
-#[derive(Deserialize, Serialize)]
+/// Deletes a value under HKEY_CURRENT_USER, missing values are ignored.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct DeleteRegistryValueCommand {
+    /// Registry path relative to HKEY_CURRENT_USER.
     #[serde(deserialize_with = "expand_string_deserializer")]
     reg_path: String,
 
+    /// Name of the value to delete.
     #[serde(deserialize_with = "expand_string_deserializer")]
     key_name: String,
 }
@@ -23,41 +32,41 @@ impl DeleteRegistryValueCommand {
     pub fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         match action {
             InstallActionType::INSTALL => {}
-            _ => return Ok(true),
+            _ => return Ok(()),
         }
 
-        let hklm = RegKey::predef(HKEY_CURRENT_USER);
+        if is_dry_run() {
+            print_planned_step(
+                format!(
+                    "delete_reg_key: HKEY_CURRENT_USER\\{} \"{}\"",
+                    self.reg_path, self.key_name
+                )
+                .as_str(),
+            );
+            return Ok(());
+        }
 
-        match hklm.open_subkey_with_flags(&self.reg_path, KEY_ALL_ACCESS) {
-            Ok(subkey) => match subkey.delete_value(&self.key_name) {
-                Err(err) => match err.kind() {
-                    io::ErrorKind::NotFound => {}
-                    _ => {
-                        return Err(format!(
-                            "Failed to delete registry key: \"{}\" path: \"{}\" err: {}",
-                            self.key_name, self.reg_path, err
-                        )
-                        .into());
-                    }
-                },
-                _ => {}
-            },
+        // a missing path or value is already deleted
+        match registry_store().delete_value(&self.reg_path, &self.key_name) {
             Err(err) => match err.kind() {
                 io::ErrorKind::NotFound => {}
                 _ => {
-                    return Err(format!(
-                        "Failed to open registry key: \"{}\" path: \"{}\" err: {}",
-                        self.key_name, self.reg_path, err
-                    )
-                    .into());
+                    return Err(InstallError::Registry {
+                        location: location.clone(),
+                        reg_path: self.reg_path.clone(),
+                        key_name: self.key_name.clone(),
+                        source: err,
+                    });
                 }
             },
+            _ => {}
         }
 
-        return Ok(true);
+        return Ok(());
     }
 }
 
@@ -71,20 +80,36 @@ impl ActionFn for DeleteRegistryValueCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute DeleteRegistryValueCommand");
 
         match from_value::<DeleteRegistryValueCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location);
             }
             Err(err) => {
-                return Err(format!(
-                    "Failed to convert data to DeleteRegistryValueCommand, err: {}",
-                    err
-                )
-                .into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to convert data to DeleteRegistryValueCommand, err: {}",
+                        err
+                    ),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<DeleteRegistryValueCommand>(
+            json_data,
+            "DeleteRegistryValueCommand",
+            location,
+            validator,
+        );
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<DeleteRegistryValueCommand>();
+    }
 }
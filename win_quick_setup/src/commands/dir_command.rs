@@ -1,16 +1,27 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
+use super::common::{
+    expand_string_deserializer, is_dry_run, print_planned_step, ActionFn, InstallActionType,
+    StepLocation,
+};
+use super::errors::InstallError;
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
 use std::fs;
 
 use log::debug;
-#[derive(Deserialize, Serialize)]
+/// Creates a directory and all of its parents.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct DirCommand {
+    /// Directory to create.
     #[serde(deserialize_with = "expand_string_deserializer")]
     path: String,
 
+    /// Remove the directory and its content before creating it again.
     #[serde(default = "default_overwrite_option")]
     should_overwrite: bool,
 }
@@ -23,40 +34,49 @@ impl DirCommand {
     pub fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         match action {
             InstallActionType::INSTALL => {}
             _ => {
-                return Ok(true);
+                return Ok(());
+            }
+        }
+
+        if is_dry_run() {
+            if self.should_overwrite {
+                print_planned_step(format!("dir: remove \"{}\" recursively", self.path).as_str());
             }
+            print_planned_step(format!("dir: create \"{}\"", self.path).as_str());
+            return Ok(());
         }
 
         if self.should_overwrite {
             match fs::remove_dir_all(&self.path) {
                 Ok(_) => {}
                 Err(err) => {
-                    return Err(format!(
-                        "Failed to cleanup directory: \"{}\" err: {}",
-                        self.path, err
-                    )
-                    .into());
+                    return Err(InstallError::Filesystem {
+                        location: location.clone(),
+                        path: self.path.clone(),
+                        source: err,
+                    });
                 }
             }
         }
 
         match fs::create_dir_all(&self.path) {
             Ok(()) => {
-                return Ok(true);
+                return Ok(());
             }
             Err(err) => {
                 if err.kind() == std::io::ErrorKind::AlreadyExists {
-                    return Ok(true);
+                    return Ok(());
                 } else {
-                    return Err(format!(
-                        "Failed to create directorie: \"{}\" err: {}",
-                        self.path, err
-                    )
-                    .into());
+                    return Err(InstallError::Filesystem {
+                        location: location.clone(),
+                        path: self.path.clone(),
+                        source: err,
+                    });
                 }
             }
         }
@@ -73,16 +93,28 @@ impl ActionFn for DirCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute DirCommand");
 
         match from_value::<DirCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location);
             }
             Err(err) => {
-                return Err(format!("Failed to convert data to DirCommand, err: {}", err).into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to DirCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<DirCommand>(json_data, "DirCommand", location, validator);
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<DirCommand>();
+    }
 }
@@ -0,0 +1,245 @@
+use super::common::StepLocation;
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+// every failure of the rendering engine, each variant points to the step that caused it
+#[derive(Debug)]
+pub enum InstallError {
+    // invalid json, invalid step structure or step data not matching its command
+    Parse {
+        location: StepLocation,
+        message: String,
+    },
+    // step key not known by the ExecutorFactory
+    UnknownCommand {
+        location: StepLocation,
+        name: String,
+    },
+    // process could not be started or waited for
+    Spawn {
+        location: StepLocation,
+        command: String,
+        source: io::Error,
+    },
+    // process finished unsuccessfully, code is None if it got terminated by a signal
+    ExitCode {
+        location: StepLocation,
+        command: String,
+        code: Option<i32>,
+    },
+    Registry {
+        location: StepLocation,
+        reg_path: String,
+        key_name: String,
+        source: io::Error,
+    },
+    // included config could not be found or loaded
+    Include {
+        location: StepLocation,
+        config_path: String,
+        message: String,
+    },
+    Filesystem {
+        location: StepLocation,
+        path: String,
+        source: io::Error,
+    },
+    // command can't run on the current platform
+    Unsupported {
+        location: StepLocation,
+        message: String,
+    },
+    // spawned task panicked or got cancelled
+    Task {
+        location: StepLocation,
+        message: String,
+    },
+    // process got killed after running longer than the step timeout, in seconds
+    Timeout {
+        location: StepLocation,
+        command: String,
+        timeout: u64,
+    },
+    // run interrupted with Ctrl-C
+    Cancelled {
+        location: StepLocation,
+    },
+    // output of a process couldn't be stored in the install value key
+    Capture {
+        location: StepLocation,
+        command: String,
+        key: String,
+        message: String,
+    },
+    // step needs form a cycle or name a step id that never showed up
+    Dependency {
+        location: StepLocation,
+        message: String,
+    },
+}
+
+impl InstallError {
+    pub fn location(&self) -> &StepLocation {
+        match self {
+            InstallError::Parse { location, .. } => location,
+            InstallError::UnknownCommand { location, .. } => location,
+            InstallError::Spawn { location, .. } => location,
+            InstallError::ExitCode { location, .. } => location,
+            InstallError::Registry { location, .. } => location,
+            InstallError::Include { location, .. } => location,
+            InstallError::Filesystem { location, .. } => location,
+            InstallError::Unsupported { location, .. } => location,
+            InstallError::Task { location, .. } => location,
+            InstallError::Timeout { location, .. } => location,
+            InstallError::Cancelled { location } => location,
+            InstallError::Capture { location, .. } => location,
+            InstallError::Dependency { location, .. } => location,
+        }
+    }
+
+    // process exit code of win_quick_setup when the run stops on this error, codes used outside
+    // of the rendering engine are listed in main.rs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InstallError::Parse { .. } => 3,
+            InstallError::UnknownCommand { .. } => 4,
+            InstallError::ExitCode { .. } => 5,
+            InstallError::Spawn { .. } => 6,
+            InstallError::Registry { .. } => 7,
+            InstallError::Include { .. } => 8,
+            InstallError::Filesystem { .. } => 9,
+            InstallError::Unsupported { .. } => 10,
+            InstallError::Task { .. } => 11,
+            InstallError::Timeout { .. } => 13,
+            InstallError::Cancelled { .. } => 14,
+            InstallError::Capture { .. } => 15,
+            InstallError::Dependency { .. } => 16,
+        }
+    }
+}
+
+impl fmt::Display for InstallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstallError::Parse { location, message } => {
+                return write!(f, "{}: {}", location, message);
+            }
+            InstallError::UnknownCommand { location, name } => {
+                return write!(f, "{}: Unknown command \"{}\"", location, name);
+            }
+            InstallError::Spawn {
+                location,
+                command,
+                source,
+            } => {
+                return write!(
+                    f,
+                    "{}: Failed to run command: \"{}\" err: {}",
+                    location, command, source
+                );
+            }
+            InstallError::ExitCode {
+                location,
+                command,
+                code,
+            } => match code {
+                Some(code) => {
+                    return write!(
+                        f,
+                        "{}: Command \"{}\" failed with exit code {}",
+                        location, command, code
+                    );
+                }
+                None => {
+                    return write!(
+                        f,
+                        "{}: Command \"{}\" was terminated before finishing",
+                        location, command
+                    );
+                }
+            },
+            InstallError::Registry {
+                location,
+                reg_path,
+                key_name,
+                source,
+            } => {
+                return write!(
+                    f,
+                    "{}: Registry operation failed, key: \"{}\" path: \"{}\" err: {}",
+                    location, key_name, reg_path, source
+                );
+            }
+            InstallError::Include {
+                location,
+                config_path,
+                message,
+            } => {
+                return write!(
+                    f,
+                    "{}: Failed to include config \"{}\": {}",
+                    location, config_path, message
+                );
+            }
+            InstallError::Filesystem {
+                location,
+                path,
+                source,
+            } => {
+                return write!(
+                    f,
+                    "{}: Filesystem operation failed for \"{}\" err: {}",
+                    location, path, source
+                );
+            }
+            InstallError::Unsupported { location, message } => {
+                return write!(f, "{}: {}", location, message);
+            }
+            InstallError::Task { location, message } => {
+                return write!(f, "{}: {}", location, message);
+            }
+            InstallError::Timeout {
+                location,
+                command,
+                timeout,
+            } => {
+                return write!(
+                    f,
+                    "{}: Command \"{}\" killed after running for more than {} seconds",
+                    location, command, timeout
+                );
+            }
+            InstallError::Cancelled { location } => {
+                return write!(f, "{}: Interrupted by user", location);
+            }
+            InstallError::Capture {
+                location,
+                command,
+                key,
+                message,
+            } => {
+                return write!(
+                    f,
+                    "{}: Failed to capture the output of \"{}\" into \"{}\": {}",
+                    location, command, key, message
+                );
+            }
+            InstallError::Dependency { location, message } => {
+                return write!(f, "{}: {}", location, message);
+            }
+        }
+    }
+}
+
+impl Error for InstallError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InstallError::Spawn { source, .. } => Some(source),
+            InstallError::Registry { source, .. } => Some(source),
+            InstallError::Filesystem { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,30 +1,58 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
-
+use super::capture::ProcessCapture;
+use super::common::{
+    expand_string_deserializer, is_dry_run, print_planned_step, run_process, ActionFn,
+    InstallActionType, StepLocation,
+};
+use super::errors::InstallError;
+use super::guards::StepGuards;
+use super::process_runner::ProcessSpec;
+
+use super::super::summary::{record_step, StepOutcome};
+use super::super::validation::{validate_step_data, Validator};
+
+use chrono::Local;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::{from_value, Value};
+use serde_json::{from_value, json, Value};
 use std::env;
-use std::error::Error;
-use std::process::Command;
 
 use async_trait::async_trait;
-use log::{debug, warn};
+use log::{debug, info, warn};
 
-#[derive(Deserialize, Serialize)]
+/// Runs an executable directly, without a shell.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct ExecCommand {
+    /// Command line run on install.
     #[serde(deserialize_with = "expand_string_deserializer")]
     install_run: String,
 
+    /// Command line run on uninstall.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_uninstall_run")]
     uninstall_run: String,
 
+    /// Command line run on update.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_update_run")]
     update_run: String,
 
+    /// Working directory, defaults to the current directory.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_dir")]
+    #[schemars(default = "default_dir_schema")]
     dir: String,
+
+    /// Seconds after which the process and all of its children get killed and the step fails.
+    #[serde(default)]
+    timeout: Option<u64>,
+
+    #[serde(flatten)]
+    capture: ProcessCapture,
+
+    #[serde(flatten)]
+    guards: StepGuards,
 }
 
 fn default_uninstall_run() -> String {
@@ -35,6 +63,11 @@ fn default_update_run() -> String {
     return String::new();
 }
 
+// the real default depends on where win_quick_setup gets started from
+fn default_dir_schema() -> String {
+    return String::from(".");
+}
+
 fn default_dir() -> String {
     if let Ok(current_dir) = env::current_dir() {
         return current_dir.to_string_lossy().to_string();
@@ -45,10 +78,51 @@ fn default_dir() -> String {
 }
 
 impl ExecCommand {
-    pub fn execute(
+    fn parse_command_line(
+        command_line: &str,
+        location: &StepLocation,
+    ) -> Result<(String, Vec<String>), InstallError> {
+        match shell_words::split(command_line).map(|parsed| {
+            parsed
+                .split_first()
+                .map(|(exec, args)| (exec.to_string(), args.to_vec()))
+        }) {
+            Ok(Some((program, args))) => return Ok((program, args)),
+            Err(err) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to parse command line: \"{}\" err: {}",
+                        command_line, err
+                    ),
+                });
+            }
+            Ok(None) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to parse command line: \"{}\"", command_line),
+                });
+            }
+        }
+    }
+
+    fn build_command(
+        &self,
+        command_line: &str,
+        location: &StepLocation,
+    ) -> Result<ProcessSpec, InstallError> {
+        let (program, args) = ExecCommand::parse_command_line(command_line, location)?;
+
+        return Ok(ProcessSpec::new(&program).args(&args).dir(&self.dir));
+    }
+
+    pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        let started = Local::now();
+
         let exec: &String;
         match action {
             InstallActionType::INSTALL => {
@@ -65,34 +139,42 @@ impl ExecCommand {
         debug!("Executing command: \"{}\"", exec);
 
         if exec.len() == 0 {
-            return Ok(true);
+            return Ok(());
         }
 
-        match shell_words::split(&exec).map(|parsed| {
-            parsed
-                .split_first()
-                .map(|(exec, args)| (exec.to_string(), args.to_vec()))
-        }) {
-            Ok(Some((exec, args))) => {
-                let status = Command::new(&exec)
-                    .args(args)
-                    .current_dir(&self.dir)
-                    .spawn()
-                    .expect("Failed to execute process")
-                    .wait()
-                    .expect("Failed to wait for process");
-
-                return Ok(status.success());
-            }
-            Err(err) => {
-                return Err(
-                    format!("Failed to parse command line: \"{}\" err: {}", exec, err).into(),
-                );
-            }
-            Ok(None) => {
-                return Err(format!("Failed to parse command line: \"{}\"", exec).into());
-            }
+        let spec = self.build_command(exec, location)?;
+
+        let probe_command = |command_line: &str| self.build_command(command_line, location);
+        if let Some(reason) = self
+            .guards
+            .skip_reason(&self.dir, &probe_command, self.timeout, location)
+            .await?
+        {
+            info!("Skipping step {}, {}", location, reason);
+            record_step(
+                location,
+                "exec",
+                json!(self),
+                started,
+                StepOutcome::Skipped(reason),
+            );
+            return Ok(());
         }
+
+        if is_dry_run() {
+            print_planned_step(
+                format!("exec: {} (dir: \"{}\")", spec.command_line(), self.dir).as_str(),
+            );
+            return Ok(());
+        }
+
+        let spec = spec.capture(
+            self.capture.captures_stdout(),
+            self.capture.captures_stderr(),
+        );
+        let output = run_process(&spec, exec, self.timeout, location).await?;
+
+        return self.capture.store(&output, exec, location);
     }
 }
 
@@ -104,16 +186,37 @@ impl ActionFn for ExecCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute ExecCommand");
 
         match from_value::<ExecCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(format!("Failed to convert data to ExecCommand, err: {}", err).into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to ExecCommand, err: {}", err),
+                });
+            }
+        }
+    }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) =
+            validate_step_data::<ExecCommand>(json_data, "ExecCommand", location, validator)
+        {
+            if let Some(message) = cmd.capture.check() {
+                validator.report(InstallError::Parse {
+                    location: location.clone(),
+                    message,
+                });
             }
         }
     }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<ExecCommand>();
+    }
 }
@@ -0,0 +1,93 @@
+use lazy_static::lazy_static;
+use std::env;
+use std::fs;
+use std::thread;
+use tokio::runtime::Handle;
+use tokio::task::block_in_place;
+
+use log::debug;
+
+use super::common::{run_process, StepLocation};
+use super::process_runner::{process_runner, ProcessSpec};
+
+lazy_static! {
+    // spawns a process, so it's only computed when a config actually uses it
+    static ref IS_ADMIN: bool = check_is_admin();
+}
+
+fn hostname() -> String {
+    if let Ok(hostname) = env::var("COMPUTERNAME") {
+        return hostname;
+    }
+
+    if let Ok(hostname) = env::var("HOSTNAME") {
+        return hostname;
+    }
+
+    if let Ok(hostname) = fs::read_to_string("/etc/hostname") {
+        return hostname.trim().to_string();
+    }
+
+    return String::new();
+}
+
+fn username() -> String {
+    if let Ok(username) = env::var("USERNAME") {
+        return username;
+    }
+
+    if let Ok(username) = env::var("USER") {
+        return username;
+    }
+
+    return String::new();
+}
+
+async fn probe_is_admin() -> bool {
+    let location = StepLocation::new("IS_ADMIN");
+    let is_windows = process_runner().target_os() == "windows";
+    let spec = if is_windows {
+        // only succeeds from an elevated prompt
+        ProcessSpec::new("net").arg("session").quiet()
+    } else {
+        ProcessSpec::new("id")
+            .arg("-u")
+            .capture(true, false)
+            .quiet()
+    };
+    let command_line = spec.command_line();
+
+    match run_process(&spec, &command_line, None, &location).await {
+        Ok(output) if is_windows => return output.success(),
+        Ok(output) => return output.success() && output.stdout.trim() == "0",
+        Err(err) => {
+            debug!("Failed to check for admin rights, err: {}", err);
+            return false;
+        }
+    }
+}
+
+// facts are read while expanding strings, so the probe blocks this worker until it finishes
+fn check_is_admin() -> bool {
+    match Handle::try_current() {
+        Ok(handle) => return block_in_place(|| handle.block_on(probe_is_admin())),
+        Err(_) => return false,
+    }
+}
+
+// facts about the machine win_quick_setup runs on, OS and ARCH use the rust names,
+// ex: "windows" and "x86_64"
+pub fn get_fact(name: &str) -> Option<String> {
+    match name {
+        "OS" => return Some(env::consts::OS.to_string()),
+        "ARCH" => return Some(env::consts::ARCH.to_string()),
+        "HOSTNAME" => return Some(hostname()),
+        "USERNAME" => return Some(username()),
+        "IS_ADMIN" => return Some(IS_ADMIN.to_string()),
+        "CPU_COUNT" => match thread::available_parallelism() {
+            Ok(count) => return Some(count.to_string()),
+            Err(_) => return Some(String::from("1")),
+        },
+        _ => return None,
+    }
+}
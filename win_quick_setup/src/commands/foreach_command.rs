@@ -0,0 +1,294 @@
+use super::common::{
+    expand_string, get_install_value, remove_install_value, set_install_value,
+    substitute_placeholders, ActionFn, InstallActionType, StepLocation,
+};
+use super::errors::InstallError;
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_value, Value};
+use std::collections::HashMap;
+
+use futures::future;
+use tokio::task;
+
+use log::debug;
+
+use super::super::rendering::render_scoped;
+use super::super::schema::steps_schema;
+use super::super::validation::{validate_step_data, Validator};
+
+/// Runs a block of steps once for every item of a list.
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct ForeachCommand {
+    /// Items to loop over, a list or a string holding a json list or values separated by ";", ex: "%PACKAGES%". Object items bind each of their fields as %var.field%.
+    items: Value,
+
+    /// Name of the install value holding the current item, referenced as %var% inside run.
+    #[serde(default = "default_var")]
+    var: String,
+
+    /// Steps run for every item.
+    #[schemars(schema_with = "steps_schema")]
+    run: Value,
+
+    /// Run the items at the same time, %var% is then only replaced inside run and not stored as an install value.
+    #[serde(default)]
+    parallel: bool,
+}
+
+fn default_var() -> String {
+    return String::from("ITEM");
+}
+
+fn item_text(item: &Value) -> String {
+    match item {
+        Value::String(string) => return string.clone(),
+        _ => return item.to_string(),
+    }
+}
+
+impl ForeachCommand {
+    fn parse_error(&self, location: &StepLocation, message: String) -> InstallError {
+        return InstallError::Parse {
+            location: location.clone(),
+            message,
+        };
+    }
+
+    fn resolve_items(&self, location: &StepLocation) -> Result<Vec<Value>, InstallError> {
+        match &self.items {
+            Value::Array(items) => return Ok(items.clone()),
+            Value::String(items) => {
+                let items = expand_string(items).map_err(|err| self.parse_error(location, err))?;
+                let items = items.trim();
+
+                if items.starts_with('[') {
+                    match serde_json::from_str::<Vec<Value>>(items) {
+                        Ok(items) => return Ok(items),
+                        Err(err) => {
+                            return Err(self.parse_error(
+                                location,
+                                format!("Failed to parse foreach items \"{}\": {}", items, err),
+                            ));
+                        }
+                    }
+                }
+
+                return Ok(items
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(|item| Value::String(item.to_string()))
+                    .collect());
+            }
+            _ => {
+                return Err(self.parse_error(
+                    location,
+                    format!(
+                        "foreach items must be a list or a string, found {}",
+                        self.items
+                    ),
+                ));
+            }
+        }
+    }
+
+    // install values of one item, item strings get expanded first since their % are escaped once
+    // substituted into the block
+    fn bindings(
+        &self,
+        item: &Value,
+        location: &StepLocation,
+    ) -> Result<HashMap<String, String>, InstallError> {
+        let mut bindings = HashMap::new();
+
+        match item {
+            Value::Object(fields) => {
+                for (field, value) in fields.iter() {
+                    let value = expand_string(&item_text(value))
+                        .map_err(|err| self.parse_error(location, err))?;
+                    bindings.insert(format!("{}.{}", self.var, field), value);
+                }
+            }
+            _ => {
+                let value = expand_string(&item_text(item))
+                    .map_err(|err| self.parse_error(location, err))?;
+                bindings.insert(self.var.clone(), value);
+            }
+        }
+
+        return Ok(bindings);
+    }
+
+    // values the bindings replaced are kept in outer_values
+    async fn run_sequentially(
+        &self,
+        items: &[Value],
+        action: &InstallActionType,
+        location: &StepLocation,
+        outer_values: &mut HashMap<String, Option<Value>>,
+    ) -> Result<(), InstallError> {
+        let mut previous_bindings: HashMap<String, String> = HashMap::new();
+        for (idx, item) in items.iter().enumerate() {
+            let bindings = self.bindings(item, location)?;
+            let block = substitute_placeholders(&self.run, &bindings);
+
+            // also visible to includes and conditions inside the block, fields missing from
+            // this item must not keep the value of the previous one
+            for key in previous_bindings.keys() {
+                remove_install_value(key);
+            }
+            for (key, value) in bindings.iter() {
+                outer_values
+                    .entry(key.clone())
+                    .or_insert_with(|| get_install_value::<Value>(key));
+                set_install_value(key, value);
+            }
+
+            render_scoped(&block, action, &location.child(idx).child("run")).await?;
+            previous_bindings = bindings;
+        }
+
+        return Ok(());
+    }
+
+    pub async fn execute(
+        &self,
+        action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        if !self.run.is_array() {
+            return Err(self.parse_error(
+                location,
+                format!(
+                    "Invalid syntax, comands are supposed to be contained into an array of objects, found {}",
+                    self.run
+                ),
+            ));
+        }
+
+        let items = self.resolve_items(location)?;
+        debug!("Looping over {} items", items.len());
+
+        if !self.parallel {
+            let mut outer_values: HashMap<String, Option<Value>> = HashMap::new();
+            let result = self
+                .run_sequentially(&items, action, location, &mut outer_values)
+                .await;
+
+            // %var% gets back the value it had before the loop, ex: the item of an outer foreach
+            for (key, value) in outer_values.iter() {
+                match value {
+                    Some(value) => set_install_value(key, value),
+                    None => remove_install_value(key),
+                }
+            }
+            return result;
+        }
+
+        // bindings are checked before any item starts, a failing one leaves no task behind
+        let mut blocks = vec![];
+        for (idx, item) in items.iter().enumerate() {
+            let bindings = self.bindings(item, location)?;
+            blocks.push((
+                substitute_placeholders(&self.run, &bindings),
+                location.child(idx).child("run"),
+            ));
+        }
+
+        let mut tasks = vec![];
+        for (block, block_location) in blocks.into_iter() {
+            let cpy_action = action.clone();
+            tasks.push(task::spawn(async move {
+                return render_scoped(&block, &cpy_action, &block_location).await;
+            }));
+        }
+
+        // every item is waited for, none is left running once the step returns
+        let mut first_err = None;
+        for item_resolved in future::join_all(tasks).await {
+            let result = match item_resolved {
+                Ok(result) => result,
+                Err(err) => Err(InstallError::Task {
+                    location: location.clone(),
+                    message: format!("Foreach task failed with error: {:?}", err),
+                }),
+            };
+
+            if let Err(err) = result {
+                if first_err.is_none() {
+                    first_err = Some(err);
+                }
+            }
+        }
+
+        match first_err {
+            Some(err) => return Err(err),
+            None => return Ok(()),
+        }
+    }
+}
+
+pub struct ForeachCommandExecutor {}
+
+use async_trait::async_trait;
+
+#[async_trait]
+impl ActionFn for ForeachCommandExecutor {
+    async fn execute_command(
+        &self,
+        json_data: &Value,
+        action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        debug!("Attempting to execute ForeachCommand");
+
+        match from_value::<ForeachCommand>(json_data.clone()) {
+            Ok(cmd) => {
+                return cmd.execute(action, location).await;
+            }
+            Err(err) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to ForeachCommand, err: {}", err),
+                });
+            }
+        }
+    }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) =
+            validate_step_data::<ForeachCommand>(json_data, "ForeachCommand", location, validator)
+        {
+            // set_var steps run during validation too, so items held by them are known here
+            let items = match cmd.resolve_items(location) {
+                Ok(items) => items,
+                Err(err) => {
+                    validator.report(err);
+                    return;
+                }
+            };
+
+            for (idx, item) in items.iter().enumerate() {
+                match cmd.bindings(item, location) {
+                    Ok(bindings) => validator.validate_scoped_block(
+                        &substitute_placeholders(&cmd.run, &bindings),
+                        &location.child(idx).child("run"),
+                    ),
+                    Err(err) => validator.report(err),
+                }
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<ForeachCommand>();
+    }
+
+    fn runs_nested_steps(&self) -> bool {
+        return true;
+    }
+}
@@ -1,26 +1,35 @@
 use super::common::{
     expand_string, expand_string_deserializer, set_install_value, ActionFn, InstallActionType,
+    StepLocation,
 };
+use super::errors::InstallError;
+use super::registry_store::{registry_store, RegistryValue};
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
-
-use winreg::enums::{HKEY_CURRENT_USER, KEY_READ};
-use winreg::RegKey;
 
 use log::{debug, warn};
-#[derive(Deserialize, Serialize)]
+/// Reads a string or dword value under HKEY_CURRENT_USER into an install value.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct GetRegistryValueCommand {
+    /// Registry path relative to HKEY_CURRENT_USER.
     #[serde(deserialize_with = "expand_string_deserializer")]
     reg_path: String,
 
+    /// Name of the value to read.
     #[serde(deserialize_with = "expand_string_deserializer")]
     key_name: String,
 
+    /// Install value the result gets stored into.
     #[serde(deserialize_with = "expand_string_deserializer")]
     install_key: String,
 
+    /// Only log a warning if the value can't be read.
     #[serde(default = "default_can_fail_option")]
     can_fail: bool,
 }
@@ -30,49 +39,51 @@ fn default_can_fail_option() -> bool {
 }
 
 impl GetRegistryValueCommand {
-    fn handle_err_case(&self, err: std::io::Error) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    fn handle_err_case(
+        &self,
+        err: std::io::Error,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         if !self.can_fail {
-            return Err(format!(
-                "Failed to get registry value, key: \"{}\" path: \"{}\" err {}",
-                self.key_name, self.reg_path, err
-            )
-            .into());
+            return Err(InstallError::Registry {
+                location: location.clone(),
+                reg_path: self.reg_path.clone(),
+                key_name: self.key_name.clone(),
+                source: err,
+            });
         } else {
             warn!(
                 "Failed to get registry value, key: \"{}\" path: \"{}\" err {}",
                 self.key_name, self.reg_path, err
             );
-            return Ok(true);
+            return Ok(());
         }
     }
 
     pub fn execute(
         &self,
         _action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
-        let hklm = RegKey::predef(HKEY_CURRENT_USER);
-
-        match hklm.open_subkey_with_flags(&self.reg_path.as_str(), KEY_READ) {
-            Ok(subkey) => match subkey.get_value::<String, _>(&self.key_name.as_str()) {
-                Ok(string_value) => {
-                    set_install_value(
-                        &self.install_key.as_str(),
-                        expand_string(string_value.as_str()).as_str(),
-                    );
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        match registry_store().get_value(&self.reg_path, &self.key_name) {
+            Ok(RegistryValue::String(string_value)) => match expand_string(string_value.as_str()) {
+                Ok(expanded) => set_install_value(&self.install_key.as_str(), expanded.as_str()),
+                Err(message) => {
+                    return Err(InstallError::Parse {
+                        location: location.clone(),
+                        message,
+                    });
                 }
-                Err(_) => match subkey.get_value::<u32, _>(&self.key_name.as_str()) {
-                    Ok(dword_value) => set_install_value(&self.install_key.as_str(), dword_value),
-                    Err(err) => {
-                        return self.handle_err_case(err);
-                    }
-                },
             },
+            Ok(RegistryValue::Dword(dword_value)) => {
+                set_install_value(&self.install_key.as_str(), dword_value)
+            }
             Err(err) => {
-                return self.handle_err_case(err);
+                return self.handle_err_case(err, location);
             }
         }
 
-        return Ok(true);
+        return Ok(());
     }
 }
 
@@ -86,20 +97,40 @@ impl ActionFn for GetRegistryValueCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute GetRegistryValueCommand");
 
         match from_value::<GetRegistryValueCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location);
             }
             Err(err) => {
-                return Err(format!(
-                    "Failed to convert data to GetRegistryValueCommand, err: {}",
-                    err
-                )
-                .into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to convert data to GetRegistryValueCommand, err: {}",
+                        err
+                    ),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<GetRegistryValueCommand>(
+            json_data,
+            "GetRegistryValueCommand",
+            location,
+            validator,
+        );
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<GetRegistryValueCommand>();
+    }
+
+    fn sets_install_values(&self) -> bool {
+        return true;
+    }
 }
@@ -0,0 +1,99 @@
+use super::common::{
+    expand_optional_string_deserializer, is_dry_run, print_planned_step, run_process, StepLocation,
+};
+use super::errors::InstallError;
+use super::process_runner::ProcessSpec;
+
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+use log::debug;
+
+// guard fields shared by the steps running a process, flattened into their data, they make
+// steps safe to run again, ex: a git clone with creates set to the cloned directory
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct StepGuards {
+    /// Skip the step when this path exists, relative paths start from dir.
+    #[serde(default, deserialize_with = "expand_optional_string_deserializer")]
+    creates: Option<String>,
+
+    /// Skip the step when this command succeeds, it runs the same way as the step's own command.
+    #[serde(default, deserialize_with = "expand_optional_string_deserializer")]
+    unless: Option<String>,
+
+    /// Run the step only when this command succeeds, it runs the same way as the step's own command.
+    #[serde(default, deserialize_with = "expand_optional_string_deserializer")]
+    only_if: Option<String>,
+}
+
+impl StepGuards {
+    // what probe commands print is discarded
+    async fn probe_succeeds(
+        command_line: &str,
+        probe_command: &(dyn Fn(&str) -> Result<ProcessSpec, InstallError> + Sync),
+        timeout: Option<u64>,
+        location: &StepLocation,
+    ) -> Result<bool, InstallError> {
+        let spec = probe_command(command_line)?.quiet();
+
+        // a probe that can't be started failed, ex: checking the version of a missing tool
+        match run_process(&spec, command_line, timeout, location).await {
+            Ok(output) => {
+                debug!(
+                    "Guard command \"{}\" finished with {:?}",
+                    command_line, output.code
+                );
+                return Ok(output.success());
+            }
+            Err(InstallError::Spawn { source, .. }) => {
+                debug!(
+                    "Guard command \"{}\" failed to start: {}",
+                    command_line, source
+                );
+                return Ok(false);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    // reason the step doesn't need to run, probe_command builds the process for a command line
+    // the way the step builds its own, in a dry run only creates is checked
+    pub async fn skip_reason(
+        &self,
+        dir: &str,
+        probe_command: &(dyn Fn(&str) -> Result<ProcessSpec, InstallError> + Sync),
+        timeout: Option<u64>,
+        location: &StepLocation,
+    ) -> Result<Option<String>, InstallError> {
+        if let Some(creates) = &self.creates {
+            if Path::new(dir).join(creates).exists() {
+                return Ok(Some(format!("\"{}\" exists", creates)));
+            }
+        }
+
+        if is_dry_run() {
+            if let Some(unless) = &self.unless {
+                print_planned_step(format!("skipped if \"{}\" succeeds", unless).as_str());
+            }
+            if let Some(only_if) = &self.only_if {
+                print_planned_step(format!("run only if \"{}\" succeeds", only_if).as_str());
+            }
+            return Ok(None);
+        }
+
+        if let Some(unless) = &self.unless {
+            if StepGuards::probe_succeeds(unless, probe_command, timeout, location).await? {
+                return Ok(Some(format!("\"{}\" succeeded", unless)));
+            }
+        }
+
+        if let Some(only_if) = &self.only_if {
+            if !StepGuards::probe_succeeds(only_if, probe_command, timeout, location).await? {
+                return Ok(Some(format!("\"{}\" failed", only_if)));
+            }
+        }
+
+        return Ok(None);
+    }
+}
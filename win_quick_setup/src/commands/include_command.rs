@@ -1,6 +1,9 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
+use std::fs;
 use std::path::Path;
 
 use lazy_static::lazy_static;
@@ -9,25 +12,30 @@ use std::sync::Mutex;
 
 use log::debug;
 
-use super::common::{expand_string_deserializer, get_install_value, ActionFn, InstallActionType};
+use super::common::{
+    expand_string_deserializer, get_install_value, ActionFn, InstallActionType, StepLocation,
+};
+use super::errors::InstallError;
 
-use super::super::rendering::install_config;
+use super::super::rendering::{load_config_file, render};
+use super::super::scheduler::link_include;
+use super::super::validation::{validate_step_data, Validator};
 
 lazy_static! {
     static ref INCLUDED_CONFIGS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
 
-#[derive(Deserialize, Serialize)]
+/// Runs the steps of another config, each config is included at most once.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct IncludeCommand {
+    /// Path of the config, relative paths are resolved against the directory of the main config.
     #[serde(deserialize_with = "expand_string_deserializer")]
     config_path: String,
 }
 
 impl IncludeCommand {
-    pub async fn execute(
-        &self,
-        action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+    // canonical so every spelling of a path names the same config, ex: "a.json" and "./a.json"
+    fn resolve_config_path(&self, location: &StepLocation) -> Result<String, InstallError> {
         let path = self.config_path.clone();
         let path = Path::new(path.as_str());
 
@@ -39,12 +47,41 @@ impl IncludeCommand {
                     config_path = conf_dir.to_string() + self.config_path.as_str();
                 }
                 None => {
-                    return Err("Failed to find config dir".into());
+                    return Err(InstallError::Include {
+                        location: location.clone(),
+                        config_path: self.config_path.clone(),
+                        message: String::from("Failed to find config dir"),
+                    });
                 }
             }
         }
 
-        return install_config(&config_path, action).await;
+        match fs::canonicalize(config_path.as_str()) {
+            Ok(path) => return Ok(path.to_string_lossy().to_string()),
+            // loading it reports what's wrong with the path
+            Err(_) => return Ok(config_path),
+        }
+    }
+
+    pub async fn execute(
+        &self,
+        config_path: String,
+        action: &InstallActionType,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        match load_config_file(&config_path) {
+            Ok(json_data) => {
+                link_include(&config_path, location);
+                return render(&json_data, action, &StepLocation::new(&config_path)).await;
+            }
+            Err(err) => {
+                return Err(InstallError::Include {
+                    location: location.clone(),
+                    config_path,
+                    message: err.to_string(),
+                });
+            }
+        }
     }
 }
 
@@ -58,29 +95,63 @@ impl ActionFn for IncludeCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute IncludeCommandExecutor");
 
         match from_value::<IncludeCommand>(json_data.clone()) {
             Ok(cmd) => {
+                let config_path = cmd.resolve_config_path(location)?;
+
+                // configs being included count too, a config including itself stops there
                 {
-                    let config_path = cmd.config_path.clone();
                     let mut used_paths = INCLUDED_CONFIGS.lock().unwrap();
                     if used_paths.contains(&config_path) {
-                        return Ok(true);
+                        return Ok(());
                     }
-                    used_paths.insert(config_path);
+                    used_paths.insert(config_path.clone());
+                }
+
+                let result = cmd.execute(config_path.clone(), action, location).await;
+                // a retry of the step includes the config again
+                if result.is_err() {
+                    INCLUDED_CONFIGS.lock().unwrap().remove(&config_path);
                 }
 
-                return cmd.execute(action).await;
+                return result;
             }
             Err(err) => {
-                return Err(format!(
-                    "Failed to convert data to IncludeCommandExecutor, err: {}",
-                    err
-                )
-                .into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to convert data to IncludeCommandExecutor, err: {}",
+                        err
+                    ),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) =
+            validate_step_data::<IncludeCommand>(json_data, "IncludeCommand", location, validator)
+        {
+            match cmd.resolve_config_path(location) {
+                Ok(config_path) => {
+                    validator.validate_config(&config_path, location);
+                }
+                Err(err) => {
+                    validator.report(err);
+                }
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<IncludeCommand>();
+    }
+
+    fn runs_nested_steps(&self) -> bool {
+        return true;
+    }
 }
@@ -1,93 +1,76 @@
-use super::common::{ActionFn, InstallActionType};
-
-use std::error::Error;
+use super::common::{ActionFn, InstallActionType, StepLocation};
+use super::errors::InstallError;
 
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::{from_value, json, Value};
-
-use futures::future;
-use tokio::task;
+use serde_json::{from_value, Value};
 
 use log::debug;
 
-use super::super::rendering::render;
+use super::super::rendering::render_block;
+use super::super::scheduler::BlockMode;
+use super::super::schema::steps_schema;
+use super::super::step_options::split_step;
+use super::super::validation::{validate_step_data, Validator};
 
-#[derive(Deserialize, Serialize)]
+/// Runs steps concurrently, steps with needs wait for them.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct ParalelExecCommand {
+    /// Steps started at the same time.
+    #[schemars(schema_with = "steps_schema")]
     run: Value,
+
+    /// Most steps running at the same time, all of them if missing.
+    #[serde(default)]
+    max_concurrency: Option<usize>,
+
+    /// Start no more steps once one failed, the running ones still finish. When false every step runs and the first failure is reported at the end.
+    #[serde(default = "default_fail_fast")]
+    fail_fast: bool,
+}
+
+fn default_fail_fast() -> bool {
+    return true;
 }
 
 impl ParalelExecCommand {
-    fn handle_resolved_task<E: std::fmt::Debug>(
-        &self,
-        item_resolved: Result<Result<bool, Box<dyn Error + Send + Sync>>, E>,
-        idx: usize,
-    ) -> Result<(), Box<dyn Error + Send + Sync>> {
-        match item_resolved {
-            Ok(value) => match value {
-                Ok(ret) => {
-                    if !ret {
-                        return Err(format!("Paralel task at index {} failed", idx).into());
-                    }
-
-                    return Ok(());
-                }
-                Err(err) => {
-                    return Err(err);
-                }
-            },
-            Err(err) => {
-                return Err(format!("Task at index {} failed with error: {:?}", idx, err).into());
-            }
+    fn check(&self, location: &StepLocation) -> Result<(), InstallError> {
+        if self.max_concurrency == Some(0) {
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: String::from("max_concurrency must be at least 1"),
+            });
         }
+
+        return Ok(());
     }
+
     pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        self.check(location)?;
+
         if let Value::Array(obj) = &self.run {
-            let mut tasks = vec![];
-            for value in obj.iter() {
+            // invalid steps are reported before anything gets started
+            for (idx, value) in obj.iter().enumerate() {
                 if let Value::Object(object) = value {
-                    if object.len() != 1 {
-                        let json_string = serde_json::to_string(&object)
-                            .expect("Failed to convert JSON to string");
-                        return Err(format!("Invalid instruction found: {}", json_string).into());
-                    }
-
-                    let encapsulated_command = json!([object.clone()]);
-                    let cpy_action = action.clone();
-                    let task = task::spawn(async move {
-                        return render(&encapsulated_command, &cpy_action).await;
-                    });
-                    tasks.push(task);
-                }
-            }
-
-            let (item_resolved, idx, remaining_futures) = future::select_all(tasks).await;
-
-            if let Err(err) = self.handle_resolved_task(item_resolved, idx) {
-                return Err(err);
-            }
-
-            let mut futures = remaining_futures;
-
-            while !futures.is_empty() {
-                let (item_resolved, idx, remaining_futures) = future::select_all(futures).await;
-
-                if let Err(err) = self.handle_resolved_task(item_resolved, idx) {
-                    return Err(err);
+                    split_step(object, &location.child("run").child(idx))?;
                 }
-
-                futures = remaining_futures;
             }
-        } else {
-            let json_string =
-                serde_json::to_string(&self.run).expect("Failed to convert JSON to string");
-            return Err(format!("Invalid syntax, comands are supposed to be contained into an array of objects, found {}", json_string).into());
         }
 
-        return Ok(true);
+        let mode = BlockMode {
+            parallel: true,
+            max_concurrency: self.max_concurrency,
+            fail_fast: self.fail_fast,
+            scoped: false,
+        };
+
+        return render_block(&self.run, action, &location.child("run"), mode).await;
     }
 }
 
@@ -101,18 +84,42 @@ impl ActionFn for ParalelExecCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute ParalelExecCommand");
 
         match from_value::<ParalelExecCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action).await;
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(
-                    format!("Failed to convert data to ParalelExecCommand, err: {}", err).into(),
-                );
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to ParalelExecCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) = validate_step_data::<ParalelExecCommand>(
+            json_data,
+            "ParalelExecCommand",
+            location,
+            validator,
+        ) {
+            if let Err(err) = cmd.check(location) {
+                validator.report(err);
+            }
+            validator.validate_parallel_block(&cmd.run, &location.child("run"));
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<ParalelExecCommand>();
+    }
+
+    fn runs_nested_steps(&self) -> bool {
+        return true;
+    }
 }
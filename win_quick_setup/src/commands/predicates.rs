@@ -0,0 +1,178 @@
+use super::common::{
+    get_install_value, is_dry_run, print_planned_step, run_process, StepLocation,
+    REFRESHENV_COMMAND,
+};
+use super::errors::InstallError;
+use super::process_runner::ProcessSpec;
+use super::registry_store::registry_store;
+
+use serde_json::Value;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+// functions usable in if conditions, with their minimum and maximum number of arguments
+const PREDICATES: [(&str, usize, usize); 7] = [
+    ("exists", 1, 1),
+    ("is_dir", 1, 1),
+    ("which", 1, 1),
+    ("env_set", 1, 1),
+    ("var_set", 1, 1),
+    ("reg_exists", 1, 2),
+    ("package_installed", 2, 2),
+];
+
+pub fn check_predicate(name: &str, arg_count: usize) -> Result<(), String> {
+    match PREDICATES.iter().find(|predicate| predicate.0 == name) {
+        Some((_, min, max)) => {
+            if arg_count < *min || arg_count > *max {
+                let expected = match (min, max) {
+                    (1, 1) => String::from("1 argument"),
+                    (min, max) if min == max => format!("{} arguments", min),
+                    (min, max) => format!("{} to {} arguments", min, max),
+                };
+                return Err(format!(
+                    "{}() takes {}, found {}",
+                    name, expected, arg_count
+                ));
+            }
+
+            return Ok(());
+        }
+        None => {
+            let names: Vec<&str> = PREDICATES.iter().map(|predicate| predicate.0).collect();
+            return Err(format!(
+                "Unknown function {}(), expected one of {}",
+                name,
+                names.join(", ")
+            ));
+        }
+    }
+}
+
+// relative paths are relative to the config file, same as include
+fn config_path(path: &str) -> PathBuf {
+    if Path::new(path).is_absolute() {
+        return PathBuf::from(path);
+    }
+
+    match get_install_value::<String>("CONF_DIR") {
+        Some(conf_dir) => return Path::new(&conf_dir).join(path),
+        None => return PathBuf::from(path),
+    }
+}
+
+// searches the PATH of this process, tools installed by earlier steps of the same run are only
+// found when their installer also updated it
+fn which(command: &str) -> bool {
+    if command.contains('/') || command.contains('\\') {
+        return Path::new(command).is_file();
+    }
+
+    let extensions: Vec<String> =
+        if cfg!(target_os = "windows") && Path::new(command).extension().is_none() {
+            env::var("PATHEXT")
+                .unwrap_or_else(|_| String::from(".COM;.EXE;.BAT;.CMD"))
+                .split(';')
+                .map(String::from)
+                .collect()
+        } else {
+            vec![String::new()]
+        };
+
+    let paths = match env::var_os("PATH") {
+        Some(paths) => paths,
+        None => return false,
+    };
+
+    for dir in env::split_paths(&paths) {
+        for extension in extensions.iter() {
+            if dir.join(format!("{}{}", command, extension)).is_file() {
+                return true;
+            }
+        }
+    }
+
+    return false;
+}
+
+// a key under HKEY_CURRENT_USER, or one of its values when value_name is given
+fn reg_exists(reg_path: &str, value_name: Option<&str>) -> bool {
+    match value_name {
+        Some(value_name) => return registry_store().value_exists(reg_path, value_name),
+        None => return registry_store().key_exists(reg_path),
+    }
+}
+
+// runs the package manager the same way the winget and vcpkg steps do, a missing package
+// manager means the package isn't installed
+async fn package_installed(manager: &str, id: &str) -> Result<bool, String> {
+    let command = match manager {
+        "winget" => format!("winget list --exact --id {}", id),
+        "vcpkg" => format!("vcpkg list {}", id),
+        _ => {
+            return Err(format!(
+                "Unknown package manager \"{}\", expected winget or vcpkg",
+                manager
+            ));
+        }
+    };
+
+    // dry runs start nothing, same as the unless and only_if guards
+    if is_dry_run() {
+        print_planned_step(
+            format!(
+                "package_installed({}, {}) would run \"{}\", assuming it isn't installed",
+                manager, id, command
+            )
+            .as_str(),
+        );
+        return Ok(false);
+    }
+
+    let spec = ProcessSpec::new("powershell")
+        .arg("-Command")
+        .arg(REFRESHENV_COMMAND)
+        .arg(&command)
+        .capture(true, true)
+        .quiet();
+
+    // conditions have no location of their own, errors get reported with the step's
+    let location = StepLocation::new("package_installed");
+    match run_process(&spec, &command, None, &location).await {
+        Ok(output) => {
+            if manager == "winget" {
+                return Ok(output.success());
+            }
+
+            // vcpkg lists every installed port starting with the name, ex: "fmt:x64-windows"
+            return Ok(output.stdout.lines().any(|line| {
+                line.starts_with(&format!("{}:", id)) || line.starts_with(&format!("{}[", id))
+            }));
+        }
+        Err(InstallError::Cancelled { .. }) => {
+            return Err(format!("Cancelled while running \"{}\"", command));
+        }
+        Err(err) => {
+            debug!("Failed to run \"{}\", err: {}", command, err);
+            return Ok(false);
+        }
+    }
+}
+
+// args are already expanded, their number got checked by check_predicate while parsing
+pub async fn evaluate_predicate(name: &str, args: &[String]) -> Result<bool, String> {
+    debug!("Evaluating {}({})", name, args.join(", "));
+
+    match name {
+        "exists" => return Ok(config_path(&args[0]).exists()),
+        "is_dir" => return Ok(config_path(&args[0]).is_dir()),
+        "which" => return Ok(which(&args[0])),
+        "env_set" => return Ok(env::var_os(&args[0]).is_some()),
+        "var_set" => return Ok(get_install_value::<Value>(&args[0]).is_some()),
+        "reg_exists" => return Ok(reg_exists(&args[0], args.get(1).map(|arg| arg.as_str()))),
+        "package_installed" => return package_installed(&args[0], &args[1]).await,
+        _ => return Err(format!("Unknown function {}()", name)),
+    }
+}
@@ -0,0 +1,622 @@
+use super::common::{is_cancelled, ProcessOutput, StepLocation, PROCESS_POLL_INTERVAL};
+use super::errors::InstallError;
+
+use async_trait::async_trait;
+use futures::future::{self, AbortHandle, Abortable, Aborted};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Command as StdCommand, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, Command};
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+use log::{debug, warn};
+
+// program a step starts, built by the executors and handed to the process runner
+#[derive(Clone, Serialize)]
+pub struct ProcessSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub dir: Option<String>,
+    pub env: BTreeMap<String, String>,
+    // what went through stdout and stderr is returned in the output
+    #[serde(skip)]
+    pub capture_stdout: bool,
+    #[serde(skip)]
+    pub capture_stderr: bool,
+    // nothing the process prints is shown and it gets no stdin, ex: guard probes
+    #[serde(skip)]
+    pub quiet: bool,
+}
+
+impl ProcessSpec {
+    pub fn new(program: &str) -> ProcessSpec {
+        return ProcessSpec {
+            program: program.to_string(),
+            args: Vec::new(),
+            dir: None,
+            env: BTreeMap::new(),
+            capture_stdout: false,
+            capture_stderr: false,
+            quiet: false,
+        };
+    }
+
+    pub fn arg(mut self, arg: &str) -> ProcessSpec {
+        self.args.push(arg.to_string());
+        return self;
+    }
+
+    pub fn args<S: AsRef<str>>(mut self, args: &[S]) -> ProcessSpec {
+        self.args
+            .extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        return self;
+    }
+
+    pub fn dir(mut self, dir: &str) -> ProcessSpec {
+        self.dir = Some(dir.to_string());
+        return self;
+    }
+
+    pub fn capture(mut self, stdout: bool, stderr: bool) -> ProcessSpec {
+        self.capture_stdout = stdout;
+        self.capture_stderr = stderr;
+        return self;
+    }
+
+    pub fn quiet(mut self) -> ProcessSpec {
+        self.quiet = true;
+        return self;
+    }
+
+    // program followed by its arguments, quoted the way a shell would need them
+    pub fn command_line(&self) -> String {
+        let mut words = vec![self.program.clone()];
+        words.extend(self.args.iter().cloned());
+        return shell_words::join(words);
+    }
+}
+
+// every program started by a step goes through the runner, command_line is the one shown in
+// errors and timeout is in seconds
+#[async_trait]
+pub trait ProcessRunner: Send + Sync {
+    async fn run(
+        &self,
+        spec: &ProcessSpec,
+        command_line: &str,
+        timeout: Option<u64>,
+        location: &StepLocation,
+    ) -> Result<ProcessOutput, InstallError>;
+
+    // OS the programs run on, commands only available on windows check it
+    fn target_os(&self) -> &str {
+        return std::env::consts::OS;
+    }
+}
+
+lazy_static! {
+    static ref PROCESS_RUNNER: Mutex<Arc<dyn ProcessRunner>> =
+        Mutex::new(Arc::new(SystemProcessRunner {}));
+}
+
+pub fn set_process_runner(runner: Arc<dyn ProcessRunner>) {
+    *PROCESS_RUNNER.lock().unwrap() = runner;
+}
+
+pub fn process_runner() -> Arc<dyn ProcessRunner> {
+    return PROCESS_RUNNER.lock().unwrap().clone();
+}
+
+// starts real processes, the whole process tree gets killed once the timeout expires or the run
+// gets cancelled
+pub struct SystemProcessRunner {}
+
+// child.kill only terminates the direct child, installers tend to start their own processes,
+// other OSes start every child in its own process group so the whole group gets killed
+async fn kill_process_tree(child: &mut Child) {
+    let pid = child.id().to_string();
+    let group = format!("-{}", pid);
+    let (program, args) = if cfg!(target_os = "windows") {
+        ("taskkill", vec!["/PID", pid.as_str(), "/T", "/F"])
+    } else {
+        ("kill", vec!["-KILL", "--", group.as_str()])
+    };
+
+    match Command::new(program).args(&args).status().await {
+        Ok(status) => {
+            if !status.success() {
+                debug!("{} for process {} exited with {}", program, pid, status);
+            }
+        }
+        Err(err) => {
+            warn!(
+                "Failed to run {} for process {}, err: {}",
+                program, pid, err
+            );
+        }
+    }
+
+    // fails if the process is already gone, nothing left to do in that case
+    if let Err(err) = child.kill() {
+        debug!("Failed to kill process {}, err: {}", child.id(), err);
+    }
+
+    if let Err(err) = child.await {
+        warn!("Failed to wait for killed process, err: {}", err);
+    }
+}
+
+fn write_output(text: &[u8], to_stderr: bool) {
+    let _ = if to_stderr {
+        io::stderr().write_all(text)
+    } else {
+        io::stdout().write_all(text)
+    };
+}
+
+// how long the pipes of a finished or killed process are still read, grandchildren may keep them
+// open long after the process is gone
+const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// what went through a pipe so far stays readable after the reader gets aborted
+struct PipeReader {
+    captured: Arc<Mutex<Vec<u8>>>,
+    task: JoinHandle<Result<(), Aborted>>,
+    abort: AbortHandle,
+}
+
+// copies a pipe of the child to our own stdout or stderr while keeping what went through it
+fn tee_pipe<R: AsyncRead + Unpin + Send + 'static>(
+    pipe: Option<R>,
+    to_stderr: bool,
+    show: bool,
+) -> Option<PipeReader> {
+    let pipe = pipe?;
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let task_captured = captured.clone();
+    let (abort, registration) = AbortHandle::new_pair();
+    let read = async move {
+        let mut reader = BufReader::new(pipe);
+        let mut line: Vec<u8> = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if show {
+                        write_output(&line, to_stderr);
+                    }
+                    task_captured.lock().unwrap().extend_from_slice(&line);
+                }
+            }
+        }
+    };
+
+    return Some(PipeReader {
+        captured,
+        task: task::spawn(Abortable::new(read, registration)),
+        abort,
+    });
+}
+
+// waits at most PIPE_DRAIN_TIMEOUT for the pipe to close, then drops the reader along with the pipe
+async fn join_pipe(reader: Option<PipeReader>) -> String {
+    let reader = match reader {
+        Some(reader) => reader,
+        None => return String::new(),
+    };
+
+    if time::timeout(PIPE_DRAIN_TIMEOUT, reader.task)
+        .await
+        .is_err()
+    {
+        debug!("Pipe still open after the process finished, it stops being read");
+        reader.abort.abort();
+    }
+
+    let captured = reader.captured.lock().unwrap();
+    return String::from_utf8_lossy(&captured).to_string();
+}
+
+async fn join_pipes(stdout: Option<PipeReader>, stderr: Option<PipeReader>) -> (String, String) {
+    return future::join(join_pipe(stdout), join_pipe(stderr)).await;
+}
+
+fn output_pipe(capture: bool, quiet: bool) -> Stdio {
+    if capture {
+        return Stdio::piped();
+    }
+    if quiet {
+        return Stdio::null();
+    }
+    return Stdio::inherit();
+}
+
+#[async_trait]
+impl ProcessRunner for SystemProcessRunner {
+    async fn run(
+        &self,
+        spec: &ProcessSpec,
+        command_line: &str,
+        timeout: Option<u64>,
+        location: &StepLocation,
+    ) -> Result<ProcessOutput, InstallError> {
+        let mut command = StdCommand::new(&spec.program);
+        #[cfg(unix)]
+        command.process_group(0);
+        command
+            .args(&spec.args)
+            .envs(&spec.env)
+            .stdout(output_pipe(spec.capture_stdout, spec.quiet))
+            .stderr(output_pipe(spec.capture_stderr, spec.quiet));
+        if let Some(dir) = &spec.dir {
+            command.current_dir(dir);
+        }
+        if spec.quiet {
+            command.stdin(Stdio::null());
+        }
+
+        let mut child: Child;
+        match Command::from(command).spawn() {
+            Ok(spawned) => child = spawned,
+            Err(err) => {
+                return Err(InstallError::Spawn {
+                    location: location.clone(),
+                    command: command_line.to_string(),
+                    source: err,
+                });
+            }
+        }
+
+        let stdout_reader = tee_pipe(child.stdout.take(), false, !spec.quiet);
+        let stderr_reader = tee_pipe(child.stderr.take(), true, !spec.quiet);
+
+        let started = Instant::now();
+        loop {
+            // wakes up as soon as the process exits, the interval only bounds how late a timeout
+            // or a cancellation gets noticed
+            match time::timeout(PROCESS_POLL_INTERVAL, &mut child).await {
+                Ok(Ok(status)) => {
+                    let (stdout, stderr) = join_pipes(stdout_reader, stderr_reader).await;
+                    return Ok(ProcessOutput {
+                        code: status.code(),
+                        stdout,
+                        stderr,
+                    });
+                }
+                Ok(Err(err)) => {
+                    kill_process_tree(&mut child).await;
+                    join_pipes(stdout_reader, stderr_reader).await;
+                    return Err(InstallError::Spawn {
+                        location: location.clone(),
+                        command: command_line.to_string(),
+                        source: err,
+                    });
+                }
+                Err(_) => {}
+            }
+
+            if is_cancelled() {
+                kill_process_tree(&mut child).await;
+                join_pipes(stdout_reader, stderr_reader).await;
+                return Err(InstallError::Cancelled {
+                    location: location.clone(),
+                });
+            }
+
+            if let Some(timeout) = timeout {
+                if started.elapsed() >= Duration::from_secs(timeout) {
+                    kill_process_tree(&mut child).await;
+                    join_pipes(stdout_reader, stderr_reader).await;
+                    return Err(InstallError::Timeout {
+                        location: location.clone(),
+                        command: command_line.to_string(),
+                        timeout,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// canned result of the programs a fake response matches
+#[derive(Deserialize)]
+struct FakeResponse {
+    // regex searched in the command line of the step, before it gets wrapped in powershell or
+    // cmd, ex: "^winget install .*Git\.Git"
+    #[serde(rename = "match")]
+    pattern: String,
+    #[serde(default)]
+    exit_code: i32,
+    #[serde(default)]
+    stdout: String,
+    #[serde(default)]
+    stderr: String,
+    // answers as if the program was missing
+    #[serde(default)]
+    not_found: bool,
+}
+
+// program the fake was asked to start
+#[derive(Clone, Serialize)]
+pub struct Invocation {
+    pub location: String,
+    pub command_line: String,
+    #[serde(flatten)]
+    pub spec: ProcessSpec,
+}
+
+// starts nothing, programs get the first matching response of a script, or exit with 0 without
+// printing anything, and every invocation is recorded, lets configs run on any machine
+pub struct FakeProcessRunner {
+    responses: Vec<(Regex, FakeResponse)>,
+    invocations: Mutex<Vec<Invocation>>,
+}
+
+impl FakeProcessRunner {
+    pub fn from_file(path: &str) -> Result<FakeProcessRunner, Box<dyn Error + Send + Sync>> {
+        return FakeProcessRunner::from_script(&fs::read_to_string(path)?);
+    }
+
+    // script is a json array of responses, the first one matching a command line wins
+    pub fn from_script(script: &str) -> Result<FakeProcessRunner, Box<dyn Error + Send + Sync>> {
+        let mut responses = Vec::new();
+        for response in serde_json::from_str::<Vec<FakeResponse>>(script)? {
+            match Regex::new(&response.pattern) {
+                Ok(regex) => responses.push((regex, response)),
+                Err(err) => {
+                    return Err(format!("Invalid match \"{}\": {}", response.pattern, err).into());
+                }
+            }
+        }
+
+        return Ok(FakeProcessRunner {
+            responses,
+            invocations: Mutex::new(Vec::new()),
+        });
+    }
+
+    pub fn invocations(&self) -> Vec<Invocation> {
+        return self.invocations.lock().unwrap().clone();
+    }
+}
+
+#[async_trait]
+impl ProcessRunner for FakeProcessRunner {
+    async fn run(
+        &self,
+        spec: &ProcessSpec,
+        command_line: &str,
+        _timeout: Option<u64>,
+        location: &StepLocation,
+    ) -> Result<ProcessOutput, InstallError> {
+        if is_cancelled() {
+            return Err(InstallError::Cancelled {
+                location: location.clone(),
+            });
+        }
+
+        self.invocations.lock().unwrap().push(Invocation {
+            location: location.to_string(),
+            command_line: command_line.to_string(),
+            spec: spec.clone(),
+        });
+
+        let response = match self
+            .responses
+            .iter()
+            .find(|(regex, _)| regex.is_match(command_line))
+        {
+            Some((_, response)) => response,
+            None => {
+                debug!("No fake response for \"{}\", exiting with 0", command_line);
+                return Ok(ProcessOutput {
+                    code: Some(0),
+                    stdout: String::new(),
+                    stderr: String::new(),
+                });
+            }
+        };
+
+        if response.not_found {
+            return Err(InstallError::Spawn {
+                location: location.clone(),
+                command: command_line.to_string(),
+                source: io::Error::new(io::ErrorKind::NotFound, "program not found"),
+            });
+        }
+
+        if !spec.quiet {
+            write_output(response.stdout.as_bytes(), false);
+            write_output(response.stderr.as_bytes(), true);
+        }
+
+        return Ok(ProcessOutput {
+            code: Some(response.exit_code),
+            stdout: if spec.capture_stdout {
+                response.stdout.clone()
+            } else {
+                String::new()
+            },
+            stderr: if spec.capture_stderr {
+                response.stderr.clone()
+            } else {
+                String::new()
+            },
+        });
+    }
+
+    // configs are written for windows, the fake answers its commands anywhere
+    fn target_os(&self) -> &str {
+        return "windows";
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn runner(script: &str) -> FakeProcessRunner {
+        return FakeProcessRunner::from_script(script).unwrap();
+    }
+
+    async fn run(
+        runner: &FakeProcessRunner,
+        spec: &ProcessSpec,
+    ) -> Result<ProcessOutput, InstallError> {
+        let location = StepLocation::new("test.json").child(0);
+        return runner
+            .run(spec, &spec.command_line(), None, &location)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn first_matching_response_wins() {
+        let runner = runner(
+            r#"[
+                {"match": "^winget install .*Git", "exit_code": 3},
+                {"match": "^winget", "exit_code": 4}
+            ]"#,
+        );
+
+        let git = ProcessSpec::new("winget").args(&["install", "Git.Git"]);
+        assert_eq!(run(&runner, &git).await.unwrap().code, Some(3));
+
+        let rustup = ProcessSpec::new("winget").args(&["install", "Rustlang.Rustup"]);
+        assert_eq!(run(&runner, &rustup).await.unwrap().code, Some(4));
+    }
+
+    #[tokio::test]
+    async fn unmatched_programs_exit_with_zero() {
+        let runner = runner(r#"[{"match": "^winget", "exit_code": 1}]"#);
+
+        let output = run(&runner, &ProcessSpec::new("cargo").capture(true, true))
+            .await
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, "");
+        assert_eq!(output.stderr, "");
+    }
+
+    #[tokio::test]
+    async fn output_is_returned_only_when_captured() {
+        let runner = runner(r#"[{"match": "^git", "stdout": "git 2.40", "stderr": "warn"}]"#);
+
+        let output = run(&runner, &ProcessSpec::new("git").capture(true, false))
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "git 2.40");
+        assert_eq!(output.stderr, "");
+
+        let output = run(&runner, &ProcessSpec::new("git").quiet())
+            .await
+            .unwrap();
+        assert_eq!(output.stdout, "");
+    }
+
+    #[tokio::test]
+    async fn not_found_fails_to_spawn() {
+        let runner = runner(r#"[{"match": "^vcpkg", "not_found": true}]"#);
+
+        match run(&runner, &ProcessSpec::new("vcpkg").arg("install")).await {
+            Err(InstallError::Spawn {
+                command, source, ..
+            }) => {
+                assert_eq!(command, "vcpkg install");
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            _ => panic!("expected a spawn error"),
+        }
+    }
+
+    #[tokio::test]
+    async fn invocations_are_recorded_in_order() {
+        let runner = runner("[]");
+
+        let first = ProcessSpec::new("cmd")
+            .args(&["/C", "echo one"])
+            .dir("C:\\");
+        let second = ProcessSpec::new("powershell").arg("Get-Date");
+        run(&runner, &first).await.unwrap();
+        run(&runner, &second).await.unwrap();
+
+        let invocations = runner.invocations();
+        assert_eq!(invocations.len(), 2);
+        assert_eq!(invocations[0].location, "test.json#0");
+        assert_eq!(invocations[0].command_line, "cmd /C 'echo one'");
+        assert_eq!(invocations[0].spec.dir.as_deref(), Some("C:\\"));
+        assert_eq!(invocations[1].spec.program, "powershell");
+        assert_eq!(invocations[1].spec.args, vec!["Get-Date"]);
+    }
+
+    // a killed process still shows up until its parent reaps it
+    #[cfg(unix)]
+    fn is_running(pid: &str) -> bool {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => return !stat.contains(") Z "),
+            Err(_) => return false,
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn timeout_kills_grandchildren() {
+        let pid_file = std::env::temp_dir().join(format!("grandchild_{}", std::process::id()));
+        let pid_file = pid_file.to_string_lossy().to_string();
+        let script = format!("sleep 30 & echo $! > {}; sleep 30", pid_file);
+        let spec = ProcessSpec::new("sh")
+            .args(&["-c", script.as_str()])
+            .quiet();
+        let location = StepLocation::new("test.json").child(0);
+
+        let started = Instant::now();
+        let result = SystemProcessRunner {}
+            .run(&spec, &spec.command_line(), Some(1), &location)
+            .await;
+        assert!(matches!(result, Err(InstallError::Timeout { .. })));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let pid = fs::read_to_string(&pid_file).unwrap();
+        let _ = fs::remove_file(&pid_file);
+        time::delay_for(Duration::from_millis(200)).await;
+        assert!(!is_running(pid.trim()));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn grandchild_holding_the_pipe_does_not_keep_the_run_waiting() {
+        let spec = ProcessSpec::new("sh")
+            .args(&["-c", "echo started; sleep 30 &"])
+            .capture(true, false)
+            .quiet();
+        let location = StepLocation::new("test.json").child(0);
+
+        let started = Instant::now();
+        let output = SystemProcessRunner {}
+            .run(&spec, &spec.command_line(), None, &location)
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(output.success());
+        assert_eq!(output.stdout, "started\n");
+    }
+
+    #[test]
+    fn invalid_match_is_rejected() {
+        let err = FakeProcessRunner::from_script(r#"[{"match": "("}]"#)
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("Invalid match \"(\""));
+    }
+}
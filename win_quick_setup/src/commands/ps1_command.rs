@@ -1,35 +1,65 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType, REFRESHENV_COMMAND};
+use super::capture::ProcessCapture;
+use super::common::{
+    expand_string_deserializer, is_dry_run, print_planned_step, run_process, ActionFn,
+    InstallActionType, StepLocation, REFRESHENV_COMMAND,
+};
+use super::errors::InstallError;
+use super::guards::StepGuards;
+use super::process_runner::ProcessSpec;
 
+use super::super::summary::{record_step, StepOutcome};
+use super::super::validation::{validate_step_data, Validator};
+
+use chrono::Local;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
-use serde_json::{from_value, Value};
+use serde_json::{from_value, json, Value};
 use std::env;
-use std::error::Error;
-use std::process::Command;
 
-use log::{debug, warn};
+use log::{debug, info, warn};
 
-#[derive(Deserialize, Serialize)]
+/// Runs a command through powershell.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct PowershellCommand {
+    /// Command run on install.
     #[serde(deserialize_with = "expand_string_deserializer")]
     install_run: String,
 
+    /// Command run on uninstall.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_uninstall_run")]
     uninstall_run: String,
 
+    /// Command run on update.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_update_run")]
     update_run: String,
 
+    /// Reload environment variables with chocolatey refreshenv before running.
     #[serde(default = "default_refresh_env")]
     refresh_env: bool,
 
+    /// Split the command into program and arguments before passing it to powershell.
     #[serde(default = "default_preparse")]
     preparse: bool,
 
+    /// Working directory, defaults to the current directory.
     #[serde(deserialize_with = "expand_string_deserializer")]
     #[serde(default = "default_dir")]
+    #[schemars(default = "default_dir_schema")]
     dir: String,
+
+    /// Seconds after which the process and all of its children get killed and the step fails.
+    #[serde(default)]
+    timeout: Option<u64>,
+
+    #[serde(flatten)]
+    capture: ProcessCapture,
+
+    #[serde(flatten)]
+    guards: StepGuards,
 }
 
 fn default_uninstall_run() -> String {
@@ -48,6 +78,11 @@ fn default_preparse() -> bool {
     return true;
 }
 
+// the real default depends on where win_quick_setup gets started from
+fn default_dir_schema() -> String {
+    return String::from(".");
+}
+
 fn default_dir() -> String {
     if let Ok(current_dir) = env::current_dir() {
         return current_dir.to_string_lossy().to_string();
@@ -58,40 +93,80 @@ fn default_dir() -> String {
 }
 
 impl PowershellCommand {
-    fn run_command(
+    fn build_command(&self, exec: &String, args: &Vec<String>) -> ProcessSpec {
+        let mut spec = ProcessSpec::new("powershell").arg("-Command");
+        if self.refresh_env {
+            spec = spec.arg(REFRESHENV_COMMAND);
+        }
+        return spec.arg(exec).args(args).dir(&self.dir);
+    }
+
+    // program and arguments passed to powershell, split only when preparse is set
+    fn split_command_line(
+        &self,
+        command_line: &str,
+        location: &StepLocation,
+    ) -> Result<(String, Vec<String>), InstallError> {
+        if !self.preparse {
+            return Ok((command_line.to_string(), Vec::new()));
+        }
+
+        match shell_words::split(command_line).map(|parsed| {
+            parsed
+                .split_first()
+                .map(|(exec, args)| (exec.to_string(), args.to_vec()))
+        }) {
+            Ok(Some((exec, args))) => return Ok((exec, args)),
+            Err(err) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to parse command line: \"{}\" err: {}",
+                        command_line, err
+                    ),
+                });
+            }
+            Ok(None) => {
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to parse command line: \"{}\"", command_line),
+                });
+            }
+        }
+    }
+
+    // command_line is the step's own, shown in errors and matched by fake processes
+    async fn run_command(
         &self,
+        command_line: &str,
         exec: &String,
         args: &Vec<String>,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
-        let exitcode: Option<i32>;
-        if self.refresh_env {
-            exitcode = Command::new("powershell")
-                .arg("-Command")
-                .arg(REFRESHENV_COMMAND)
-                .arg(exec)
-                .args(args)
-                .current_dir(&self.dir)
-                .status()
-                .map(|exitcode| exitcode.code())
-                .unwrap_or(Some(-1));
-        } else {
-            exitcode = Command::new("powershell")
-                .arg("-Command")
-                .arg(exec)
-                .args(args)
-                .current_dir(&self.dir)
-                .status()
-                .map(|exitcode| exitcode.code())
-                .unwrap_or(Some(-1));
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        let spec = self.build_command(exec, args).capture(
+            self.capture.captures_stdout(),
+            self.capture.captures_stderr(),
+        );
+
+        if is_dry_run() {
+            print_planned_step(
+                format!("ps1: {} (dir: \"{}\")", spec.command_line(), self.dir).as_str(),
+            );
+            return Ok(());
         }
 
-        return Ok(exitcode.is_some_and(|x| x == 0));
+        let output = run_process(&spec, command_line, self.timeout, location).await?;
+
+        return self.capture.store(&output, command_line, location);
     }
 
-    pub fn execute(
+    pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        let started = Local::now();
+
         let exec: &String;
         match action {
             InstallActionType::INSTALL => {
@@ -105,38 +180,43 @@ impl PowershellCommand {
             }
         }
 
-        println!(
-            "Executing command: \"{}\" refresh_emv: {}",
-            exec, self.refresh_env
-        );
-
         if exec.len() == 0 {
-            return Ok(true);
+            return Ok(());
         }
 
-        if self.preparse {
-            match shell_words::split(&exec).map(|parsed| {
-                parsed
-                    .split_first()
-                    .map(|(exec, args)| (exec.to_string(), args.to_vec()))
-            }) {
-                Ok(Some((exec, args))) => {
-                    return self.run_command(&exec, &args);
-                }
-                Err(err) => {
-                    return Err(
-                        format!("Failed to parse command line: \"{}\" err: {}", exec, err).into(),
-                    );
-                }
-                Ok(None) => {
-                    return Err(format!("Failed to parse command line: \"{}\"", exec).into());
-                }
-            }
+        let (program, args) = self.split_command_line(exec, location)?;
+
+        let probe_command = |command_line: &str| {
+            let (program, args) = self.split_command_line(command_line, location)?;
+            return Ok(self.build_command(&program, &args));
+        };
+        if let Some(reason) = self
+            .guards
+            .skip_reason(&self.dir, &probe_command, self.timeout, location)
+            .await?
+        {
+            info!("Skipping step {}, {}", location, reason);
+            record_step(
+                location,
+                "ps1",
+                json!(self),
+                started,
+                StepOutcome::Skipped(reason),
+            );
+            return Ok(());
         }
 
-        return self.run_command(&exec, &Vec::new());
+        if !is_dry_run() {
+            println!(
+                "Executing command: \"{}\" refresh_emv: {}",
+                exec, self.refresh_env
+            );
+        }
+
+        return self.run_command(exec, &program, &args, location).await;
     }
 }
+
 pub struct PowershellCommandExecutor {}
 
 use async_trait::async_trait;
@@ -147,18 +227,40 @@ impl ActionFn for PowershellCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute PowershellCommand");
 
         match from_value::<PowershellCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(
-                    format!("Failed to convert data to PowershellCommand, err: {}", err).into(),
-                );
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to PowershellCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        if let Some(cmd) = validate_step_data::<PowershellCommand>(
+            json_data,
+            "PowershellCommand",
+            location,
+            validator,
+        ) {
+            if let Some(message) = cmd.capture.check() {
+                validator.report(InstallError::Parse {
+                    location: location.clone(),
+                    message,
+                });
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<PowershellCommand>();
+    }
 }
@@ -0,0 +1,370 @@
+use lazy_static::lazy_static;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+#[cfg(windows)]
+use winreg::enums::{HKEY_CURRENT_USER, KEY_ALL_ACCESS, KEY_READ, KEY_WRITE};
+#[cfg(windows)]
+use winreg::RegKey;
+
+// value types the registry steps read and write
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum RegistryValue {
+    String(String),
+    Dword(u32),
+}
+
+// registry values under HKEY_CURRENT_USER, missing paths and values fail with NotFound like they
+// do in the windows registry
+pub trait RegistryStore: Send + Sync {
+    fn key_exists(&self, reg_path: &str) -> bool;
+
+    fn value_exists(&self, reg_path: &str, key_name: &str) -> bool;
+
+    fn get_value(&self, reg_path: &str, key_name: &str) -> io::Result<RegistryValue>;
+
+    fn set_value(&self, reg_path: &str, key_name: &str, value: &RegistryValue) -> io::Result<()>;
+
+    fn delete_value(&self, reg_path: &str, key_name: &str) -> io::Result<()>;
+}
+
+lazy_static! {
+    static ref REGISTRY_STORE: Mutex<Arc<dyn RegistryStore>> = Mutex::new(default_store());
+}
+
+// the windows registry, other OSes start with an empty registry kept in memory
+#[cfg(windows)]
+fn default_store() -> Arc<dyn RegistryStore> {
+    return Arc::new(WinregStore {});
+}
+
+#[cfg(not(windows))]
+fn default_store() -> Arc<dyn RegistryStore> {
+    return Arc::new(FileRegistryStore {
+        file: None,
+        keys: Mutex::new(BTreeMap::new()),
+    });
+}
+
+pub fn set_registry_store(store: Arc<dyn RegistryStore>) {
+    *REGISTRY_STORE.lock().unwrap() = store;
+}
+
+pub fn registry_store() -> Arc<dyn RegistryStore> {
+    return REGISTRY_STORE.lock().unwrap().clone();
+}
+
+#[cfg(windows)]
+pub struct WinregStore {}
+
+#[cfg(windows)]
+impl RegistryStore for WinregStore {
+    fn key_exists(&self, reg_path: &str) -> bool {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        return hkcu.open_subkey_with_flags(reg_path, KEY_READ).is_ok();
+    }
+
+    fn value_exists(&self, reg_path: &str, key_name: &str) -> bool {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        match hkcu.open_subkey_with_flags(reg_path, KEY_READ) {
+            Ok(subkey) => return subkey.get_raw_value(key_name).is_ok(),
+            Err(_) => return false,
+        }
+    }
+
+    fn get_value(&self, reg_path: &str, key_name: &str) -> io::Result<RegistryValue> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let subkey = hkcu.open_subkey_with_flags(reg_path, KEY_READ)?;
+        match subkey.get_value::<String, _>(key_name) {
+            Ok(string_value) => return Ok(RegistryValue::String(string_value)),
+            Err(_) => return Ok(RegistryValue::Dword(subkey.get_value::<u32, _>(key_name)?)),
+        }
+    }
+
+    fn set_value(&self, reg_path: &str, key_name: &str, value: &RegistryValue) -> io::Result<()> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let subkey = hkcu.open_subkey_with_flags(reg_path, KEY_WRITE)?;
+        match value {
+            RegistryValue::String(string_value) => {
+                return subkey.set_value(key_name, &string_value.as_str());
+            }
+            RegistryValue::Dword(dword_value) => return subkey.set_value(key_name, dword_value),
+        }
+    }
+
+    fn delete_value(&self, reg_path: &str, key_name: &str) -> io::Result<()> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        let subkey = hkcu.open_subkey_with_flags(reg_path, KEY_ALL_ACCESS)?;
+        return subkey.delete_value(key_name);
+    }
+}
+
+type RegistryKeys = BTreeMap<String, BTreeMap<String, RegistryValue>>;
+
+// registry kept in memory, saved to a json object of paths holding objects of values after every
+// change when it has a file, ex: {"Software\\Foo": {"Name": "text", "Count": 3}}, values can
+// only be written to paths already in it, the way windows needs the key to exist
+pub struct FileRegistryStore {
+    file: Option<String>,
+    keys: Mutex<RegistryKeys>,
+}
+
+fn not_found(what: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::NotFound, format!("{} not found", what));
+}
+
+impl FileRegistryStore {
+    // a missing file starts an empty registry
+    pub fn from_file(file: &str) -> io::Result<FileRegistryStore> {
+        let mut keys = BTreeMap::new();
+        if Path::new(file).exists() {
+            let contents = fs::read_to_string(file)?;
+            keys = serde_json::from_str::<RegistryKeys>(&contents)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        }
+
+        return Ok(FileRegistryStore {
+            file: Some(file.to_string()),
+            keys: Mutex::new(keys),
+        });
+    }
+
+    fn save(&self, keys: &RegistryKeys) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            let contents = serde_json::to_string_pretty(keys)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            fs::write(file, contents)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl RegistryStore for FileRegistryStore {
+    fn key_exists(&self, reg_path: &str) -> bool {
+        return self.keys.lock().unwrap().contains_key(reg_path);
+    }
+
+    fn value_exists(&self, reg_path: &str, key_name: &str) -> bool {
+        match self.keys.lock().unwrap().get(reg_path) {
+            Some(values) => return values.contains_key(key_name),
+            None => return false,
+        }
+    }
+
+    fn get_value(&self, reg_path: &str, key_name: &str) -> io::Result<RegistryValue> {
+        let keys = self.keys.lock().unwrap();
+        match keys.get(reg_path) {
+            Some(values) => match values.get(key_name) {
+                Some(value) => return Ok(value.clone()),
+                None => return Err(not_found("Registry value")),
+            },
+            None => return Err(not_found("Registry path")),
+        }
+    }
+
+    fn set_value(&self, reg_path: &str, key_name: &str, value: &RegistryValue) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.get_mut(reg_path) {
+            Some(values) => {
+                values.insert(key_name.to_string(), value.clone());
+            }
+            None => return Err(not_found("Registry path")),
+        }
+
+        return self.save(&keys);
+    }
+
+    fn delete_value(&self, reg_path: &str, key_name: &str) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.get_mut(reg_path) {
+            Some(values) => {
+                if values.remove(key_name).is_none() {
+                    return Err(not_found("Registry value"));
+                }
+            }
+            None => return Err(not_found("Registry path")),
+        }
+
+        return self.save(&keys);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixture {
+        file: String,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Fixture {
+            let file = std::env::temp_dir()
+                .join(format!("registry_{}_{}.json", std::process::id(), name))
+                .to_string_lossy()
+                .to_string();
+            let _ = fs::remove_file(&file);
+            return Fixture { file };
+        }
+
+        // a file holding the empty path Software\\Foo
+        fn with_foo(name: &str) -> Fixture {
+            let fixture = Fixture::new(name);
+            fs::write(&fixture.file, r#"{"Software\\Foo": {}}"#).unwrap();
+            return fixture;
+        }
+
+        fn store(&self) -> FileRegistryStore {
+            return FileRegistryStore::from_file(&self.file).unwrap();
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.file);
+        }
+    }
+
+    fn string(value: io::Result<RegistryValue>) -> String {
+        match value.unwrap() {
+            RegistryValue::String(string_value) => return string_value,
+            RegistryValue::Dword(_) => panic!("expected a string value"),
+        }
+    }
+
+    #[test]
+    fn missing_file_starts_empty() {
+        let fixture = Fixture::new("missing");
+        let store = fixture.store();
+
+        assert!(!store.key_exists("Software\\Foo"));
+        assert!(!store.value_exists("Software\\Foo", "Name"));
+        assert!(!Path::new(&fixture.file).exists());
+    }
+
+    #[test]
+    fn set_then_get() {
+        let fixture = Fixture::with_foo("set_get");
+        let store = fixture.store();
+
+        store
+            .set_value(
+                "Software\\Foo",
+                "Name",
+                &RegistryValue::String("text".into()),
+            )
+            .unwrap();
+        store
+            .set_value("Software\\Foo", "Count", &RegistryValue::Dword(3))
+            .unwrap();
+
+        assert!(store.key_exists("Software\\Foo"));
+        assert!(store.value_exists("Software\\Foo", "Name"));
+        assert_eq!(string(store.get_value("Software\\Foo", "Name")), "text");
+        match store.get_value("Software\\Foo", "Count").unwrap() {
+            RegistryValue::Dword(dword_value) => assert_eq!(dword_value, 3),
+            RegistryValue::String(_) => panic!("expected a dword value"),
+        }
+
+        store
+            .set_value(
+                "Software\\Foo",
+                "Name",
+                &RegistryValue::String("other".into()),
+            )
+            .unwrap();
+        assert_eq!(string(store.get_value("Software\\Foo", "Name")), "other");
+    }
+
+    #[test]
+    fn delete_removes_only_the_value() {
+        let fixture = Fixture::with_foo("delete");
+        let store = fixture.store();
+
+        store
+            .set_value(
+                "Software\\Foo",
+                "Name",
+                &RegistryValue::String("text".into()),
+            )
+            .unwrap();
+        store.delete_value("Software\\Foo", "Name").unwrap();
+
+        assert!(store.key_exists("Software\\Foo"));
+        assert!(!store.value_exists("Software\\Foo", "Name"));
+        let err = store.delete_value("Software\\Foo", "Name").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn missing_path_and_value_are_not_found() {
+        let fixture = Fixture::with_foo("not_found");
+        let store = fixture.store();
+        store
+            .set_value(
+                "Software\\Foo",
+                "Name",
+                &RegistryValue::String("text".into()),
+            )
+            .unwrap();
+
+        let err = store.get_value("Software\\Bar", "Name").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = store.get_value("Software\\Foo", "Other").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = store.delete_value("Software\\Bar", "Name").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn set_value_does_not_create_the_path() {
+        let fixture = Fixture::with_foo("no_create");
+        let store = fixture.store();
+
+        let err = store
+            .set_value(
+                "Software\\Bar",
+                "Name",
+                &RegistryValue::String("text".into()),
+            )
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(!store.key_exists("Software\\Bar"));
+        assert!(!fixture.store().key_exists("Software\\Bar"));
+    }
+
+    #[test]
+    fn changes_are_saved_to_the_file() {
+        let fixture = Fixture::with_foo("saved");
+        fixture
+            .store()
+            .set_value(
+                "Software\\Foo",
+                "Name",
+                &RegistryValue::String("text".into()),
+            )
+            .unwrap();
+
+        let reopened = fixture.store();
+        assert_eq!(string(reopened.get_value("Software\\Foo", "Name")), "text");
+
+        reopened.delete_value("Software\\Foo", "Name").unwrap();
+        assert!(!fixture.store().value_exists("Software\\Foo", "Name"));
+    }
+
+    #[test]
+    fn invalid_file_is_rejected() {
+        let fixture = Fixture::new("invalid");
+        fs::write(&fixture.file, "[1, 2]").unwrap();
+
+        let err = FileRegistryStore::from_file(&fixture.file).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,21 +1,31 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
+use super::common::{
+    expand_string_deserializer, is_dry_run, print_planned_step, ActionFn, InstallActionType,
+    StepLocation,
+};
+use super::errors::InstallError;
+use super::registry_store::{registry_store, RegistryValue};
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
-
-use winreg::enums::{HKEY_CURRENT_USER, KEY_WRITE};
-use winreg::RegKey;
 
 use log::debug;
-#[derive(Deserialize, Serialize)]
+/// Writes a string value under HKEY_CURRENT_USER.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct UpdateRegistryCommand {
+    /// Registry path relative to HKEY_CURRENT_USER.
     #[serde(deserialize_with = "expand_string_deserializer")]
     reg_path: String,
 
+    /// Name of the value to write.
     #[serde(deserialize_with = "expand_string_deserializer")]
     key_name: String,
 
+    /// String to write.
     #[serde(deserialize_with = "expand_string_deserializer")]
     value: String,
 }
@@ -24,33 +34,36 @@ impl UpdateRegistryCommand {
     pub fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         match action {
             InstallActionType::INSTALL => {}
-            _ => return Ok(true),
+            _ => return Ok(()),
         }
 
-        let hklm = RegKey::predef(HKEY_CURRENT_USER);
+        if is_dry_run() {
+            print_planned_step(
+                format!(
+                    "set_reg_val: HKEY_CURRENT_USER\\{} \"{}\" = \"{}\"",
+                    self.reg_path, self.key_name, self.value
+                )
+                .as_str(),
+            );
+            return Ok(());
+        }
 
-        match hklm.open_subkey_with_flags(&self.reg_path, KEY_WRITE) {
-            Ok(subkey) => match subkey.set_value(&self.key_name, &self.value.as_str()) {
-                Ok(_) => {
-                    return Ok(true);
-                }
-                Err(err) => {
-                    return Err(format!(
-                        "Failed to delete registry key: \"{}\" path: \"{}\" err: {}",
-                        self.key_name, self.reg_path, err
-                    )
-                    .into());
-                }
-            },
+        let value = RegistryValue::String(self.value.clone());
+        match registry_store().set_value(&self.reg_path, &self.key_name, &value) {
+            Ok(_) => {
+                return Ok(());
+            }
             Err(err) => {
-                return Err(format!(
-                    "Failed to open registry key: \"{}\" path: \"{}\" err: {}",
-                    self.key_name, self.reg_path, err
-                )
-                .into());
+                return Err(InstallError::Registry {
+                    location: location.clone(),
+                    reg_path: self.reg_path.clone(),
+                    key_name: self.key_name.clone(),
+                    source: err,
+                });
             }
         }
     }
@@ -66,20 +79,36 @@ impl ActionFn for UpdateRegistryCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute UpdateRegistryCommand");
 
         match from_value::<UpdateRegistryCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location);
             }
             Err(err) => {
-                return Err(format!(
-                    "Failed to convert data to UpdateRegistryCommand, err: {}",
-                    err
-                )
-                .into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!(
+                        "Failed to convert data to UpdateRegistryCommand, err: {}",
+                        err
+                    ),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<UpdateRegistryCommand>(
+            json_data,
+            "UpdateRegistryCommand",
+            location,
+            validator,
+        );
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<UpdateRegistryCommand>();
+    }
 }
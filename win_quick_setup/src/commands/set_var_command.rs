@@ -1,31 +1,46 @@
-use super::common::{expand_string_deserializer, set_install_value, ActionFn, InstallActionType};
+use super::common::{
+    expand_string_deserializer, set_install_value, ActionFn, InstallActionType, StepLocation,
+};
+use super::errors::InstallError;
 
-use serde::Serialize;
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Schema, SchemaObject, SubschemaValidation};
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
 
 use log::debug;
-#[derive(Deserialize, Serialize)]
-struct SetVarCommand<T: Clone + Serialize> {
+/// Stores a bool, number or list install value, lists are used as foreach items.
+#[derive(Deserialize, Serialize, JsonSchema)]
+struct SetVarCommand<T: Clone + serde::Serialize> {
+    /// Name of the install value, referenced as %key% in other steps.
     key: String,
+
+    /// Value to store.
     value: T,
 }
 
-impl<T: Clone + Serialize> SetVarCommand<T> {
+impl<T: Clone + serde::Serialize> SetVarCommand<T> {
     pub fn execute(
         &self,
         _action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        _location: &StepLocation,
+    ) -> Result<(), InstallError> {
         set_install_value(&self.key.as_str(), self.value.clone());
 
-        return Ok(true);
+        return Ok(());
     }
 }
 
-#[derive(Deserialize, Serialize)]
+/// Stores a string install value.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct SetStringVarCommand {
+    /// Name of the install value, referenced as %key% in other steps.
     key: String,
+
+    /// Value to store, placeholders get expanded.
     #[serde(deserialize_with = "expand_string_deserializer")]
     value: String,
 }
@@ -34,10 +49,11 @@ impl SetStringVarCommand {
     pub fn execute(
         &self,
         _action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        _location: &StepLocation,
+    ) -> Result<(), InstallError> {
         set_install_value(&self.key.as_str(), &self.value);
 
-        return Ok(true);
+        return Ok(());
     }
 }
 
@@ -51,54 +67,162 @@ impl ActionFn for SetVarCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute SetVarCommand");
 
         match json_data.get("value") {
             Some(value) => match value {
                 Value::Bool(_) => match from_value::<SetVarCommand<bool>>(json_data.clone()) {
                     Ok(cmd) => {
-                        return cmd.execute(action);
+                        return cmd.execute(action, location);
                     }
                     Err(err) => {
-                        return Err(format!(
-                            "Failed to convert data to SetVarCommand<bool>, err: {}",
-                            err
-                        )
-                        .into());
+                        return Err(InstallError::Parse {
+                            location: location.clone(),
+                            message: format!(
+                                "Failed to convert data to SetVarCommand<bool>, err: {}",
+                                err
+                            ),
+                        });
                     }
                 },
                 Value::Number(_) => match from_value::<SetVarCommand<u32>>(json_data.clone()) {
                     Ok(cmd) => {
-                        return cmd.execute(action);
+                        return cmd.execute(action, location);
+                    }
+                    Err(err) => {
+                        return Err(InstallError::Parse {
+                            location: location.clone(),
+                            message: format!(
+                                "Failed to convert data to SetVarCommand<u32>, err: {}",
+                                err
+                            ),
+                        });
+                    }
+                },
+                Value::Array(_) => match from_value::<SetVarCommand<Vec<Value>>>(json_data.clone())
+                {
+                    Ok(cmd) => {
+                        return cmd.execute(action, location);
                     }
                     Err(err) => {
-                        return Err(format!(
-                            "Failed to convert data to SetVarCommand<u32>, err: {}",
-                            err
-                        )
-                        .into());
+                        return Err(InstallError::Parse {
+                            location: location.clone(),
+                            message: format!(
+                                "Failed to convert data to SetVarCommand<Vec<Value>>, err: {}",
+                                err
+                            ),
+                        });
                     }
                 },
                 Value::String(_) => match from_value::<SetStringVarCommand>(json_data.clone()) {
                     Ok(cmd) => {
-                        return cmd.execute(action);
+                        return cmd.execute(action, location);
                     }
                     Err(err) => {
-                        return Err(format!(
-                            "Failed to convert data to SetStringVarCommand, err: {}",
-                            err
-                        )
-                        .into());
+                        return Err(InstallError::Parse {
+                            location: location.clone(),
+                            message: format!(
+                                "Failed to convert data to SetStringVarCommand, err: {}",
+                                err
+                            ),
+                        });
                     }
                 },
                 _ => {
-                    return Err("Unsupported json data type found".into());
+                    return Err(InstallError::Parse {
+                        location: location.clone(),
+                        message: String::from("Unsupported json data type found"),
+                    });
                 }
             },
             _ => {
-                return Err("Key 'value' not found in JSON data".into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: String::from("Key 'value' not found in JSON data"),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        // values are still stored so later includes and conditions expand like they would at runtime
+        match json_data.get("value") {
+            Some(Value::Bool(_)) => {
+                if let Some(cmd) = validate_step_data::<SetVarCommand<bool>>(
+                    json_data,
+                    "SetVarCommand<bool>",
+                    location,
+                    validator,
+                ) {
+                    let _ = cmd.execute(&InstallActionType::INSTALL, location);
+                }
+            }
+            Some(Value::Number(_)) => {
+                if let Some(cmd) = validate_step_data::<SetVarCommand<u32>>(
+                    json_data,
+                    "SetVarCommand<u32>",
+                    location,
+                    validator,
+                ) {
+                    let _ = cmd.execute(&InstallActionType::INSTALL, location);
+                }
+            }
+            Some(Value::Array(_)) => {
+                if let Some(cmd) = validate_step_data::<SetVarCommand<Vec<Value>>>(
+                    json_data,
+                    "SetVarCommand<Vec<Value>>",
+                    location,
+                    validator,
+                ) {
+                    let _ = cmd.execute(&InstallActionType::INSTALL, location);
+                }
+            }
+            Some(Value::String(_)) => {
+                if let Some(cmd) = validate_step_data::<SetStringVarCommand>(
+                    json_data,
+                    "SetStringVarCommand",
+                    location,
+                    validator,
+                ) {
+                    let _ = cmd.execute(&InstallActionType::INSTALL, location);
+                }
+            }
+            Some(_) => {
+                validator.report(InstallError::Parse {
+                    location: location.clone(),
+                    message: String::from("Unsupported json data type found"),
+                });
+            }
+            None => {
+                validator.report(InstallError::Parse {
+                    location: location.clone(),
+                    message: String::from("Key 'value' not found in JSON data"),
+                });
+            }
+        }
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        // value type decides which of the variants gets deserialized
+        let schema = SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![
+                    generator.subschema_for::<SetVarCommand<bool>>(),
+                    generator.subschema_for::<SetVarCommand<u32>>(),
+                    generator.subschema_for::<SetVarCommand<Vec<Value>>>(),
+                    generator.subschema_for::<SetStringVarCommand>(),
+                ]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        return Schema::Object(schema);
+    }
+
+    fn sets_install_values(&self) -> bool {
+        return true;
+    }
 }
@@ -1,42 +1,60 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType, REFRESHENV_COMMAND};
+use super::common::{
+    check_process_status, expand_string_deserializer, is_dry_run, print_planned_step, run_process,
+    ActionFn, InstallActionType, StepLocation, REFRESHENV_COMMAND,
+};
+use super::errors::InstallError;
+use super::process_runner::ProcessSpec;
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
-use std::process::Command;
 
 use log::debug;
 
-#[derive(Deserialize, Serialize)]
+/// Installs, removes or upgrades a vcpkg port.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct VcpkgCommand {
+    /// Port name, ex: nlohmann-json.
     #[serde(deserialize_with = "expand_string_deserializer")]
     module: String,
+
+    /// Seconds after which the process and all of its children get killed and the step fails.
+    #[serde(default)]
+    timeout: Option<u64>,
 }
 
 impl VcpkgCommand {
-    fn run_command(
+    async fn run_command(
         &self,
         exec: &String,
         args: &Vec<String>,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
-        let exitcode: Option<i32>;
-
-        exitcode = Command::new("powershell")
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        let spec = ProcessSpec::new("powershell")
             .arg("-Command")
             .arg(REFRESHENV_COMMAND)
             .arg(exec)
-            .args(args)
-            .status()
-            .map(|exitcode| exitcode.code())
-            .unwrap_or(Some(-1));
+            .args(args);
 
-        return Ok(exitcode.is_some_and(|x| x == 0));
+        if is_dry_run() {
+            print_planned_step(format!("vcpkg: {}", spec.command_line()).as_str());
+            return Ok(());
+        }
+
+        let output = run_process(&spec, exec, self.timeout, location).await?;
+
+        return check_process_status(output.code, exec, location);
     }
 
-    pub fn execute(
+    pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         let mut exec: String = String::new();
         match action {
             InstallActionType::INSTALL => {
@@ -51,13 +69,15 @@ impl VcpkgCommand {
         }
         exec.push_str(self.module.as_str());
 
-        println!("Executing command: \"{}\"", exec);
+        if !is_dry_run() {
+            println!("Executing command: \"{}\"", exec);
+        }
 
         if exec.len() == 0 {
-            return Ok(true);
+            return Ok(());
         }
 
-        return self.run_command(&exec, &Vec::new());
+        return self.run_command(&exec, &Vec::new(), location).await;
     }
 }
 
@@ -71,16 +91,32 @@ impl ActionFn for VcpkgCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute VcpkgCommand");
 
         match from_value::<VcpkgCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(format!("Failed to convert data to VcpkgCommand, err: {}", err).into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to VcpkgCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<VcpkgCommand>(json_data, "VcpkgCommand", location, validator);
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<VcpkgCommand>();
+    }
+
+    fn default_locks(&self) -> Vec<String> {
+        return vec![String::from("vcpkg")];
+    }
 }
@@ -1,24 +1,48 @@
-use super::common::{expand_string_deserializer, ActionFn, InstallActionType};
+use super::common::{
+    check_process_status, expand_string_deserializer, is_dry_run, print_planned_step, run_process,
+    ActionFn, InstallActionType, StepLocation,
+};
+use super::errors::InstallError;
+use super::process_runner::{process_runner, ProcessSpec};
 
+use super::super::validation::{validate_step_data, Validator};
+
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
 use serde_derive::{Deserialize, Serialize};
 use serde_json::{from_value, Value};
-use std::error::Error;
-use std::process::Command;
 
 use async_trait::async_trait;
 
-use log::debug;
-#[derive(Deserialize, Serialize)]
+use log::{debug, info};
+/// Installs, removes or upgrades a winget package.
+#[derive(Deserialize, Serialize, JsonSchema)]
 struct WingetCommand {
+    /// Package id, ex: Git.Git.
     #[serde(deserialize_with = "expand_string_deserializer")]
     package: String,
+
+    /// Seconds after which the process and all of its children get killed and the step fails.
+    #[serde(default)]
+    timeout: Option<u64>,
+
+    /// Non zero exit codes that still count as success, other ones fail the step. Defaults to the codes winget returns for an already installed package (-1978335135) and when no upgrade applies (-1978335189).
+    #[serde(default = "default_success_codes")]
+    success_codes: Vec<i32>,
+}
+
+// APPINSTALLER_CLI_ERROR_PACKAGE_ALREADY_INSTALLED and APPINSTALLER_CLI_ERROR_UPDATE_NOT_APPLICABLE
+fn default_success_codes() -> Vec<i32> {
+    return vec![0x8A150061_u32 as i32, 0x8A15002B_u32 as i32];
 }
 
 impl WingetCommand {
-    pub fn execute(
+    pub async fn execute(
         &self,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         let mut exec: String = String::from("winget ");
         match action {
             InstallActionType::INSTALL => {
@@ -34,15 +58,35 @@ impl WingetCommand {
         exec.push_str(" --accept-package-agreements ");
         exec.push_str(self.package.as_str());
 
+        if is_dry_run() {
+            print_planned_step(format!("winget: cmd /C {}", shell_words::quote(&exec)).as_str());
+            return Ok(());
+        }
+
         println!("Executing command: \"{}\"", exec);
 
-        if cfg!(target_os = "windows") {
-            Command::new("cmd").args(&["/C", &exec.as_str()]).status()?;
-        } else {
-            return Err("Winget command not allowed on OS other then windows".into());
-        };
+        if process_runner().target_os() != "windows" {
+            return Err(InstallError::Unsupported {
+                location: location.clone(),
+                message: String::from("Winget command not allowed on OS other then windows"),
+            });
+        }
+
+        let spec = ProcessSpec::new("cmd").args(&["/C", exec.as_str()]);
+        let output = run_process(&spec, &exec, self.timeout, location).await?;
 
-        return Ok(true);
+        // winget reports already installed or up to date packages with a non zero code
+        if let Some(code) = output.code {
+            if code != 0 && self.success_codes.contains(&code) {
+                info!(
+                    "Command \"{}\" exited with code {}, nothing to do",
+                    exec, code
+                );
+                return Ok(());
+            }
+        }
+
+        return check_process_status(output.code, &exec, location);
     }
 }
 
@@ -54,16 +98,32 @@ impl ActionFn for WingetCommandExecutor {
         &self,
         json_data: &Value,
         action: &InstallActionType,
-    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
         debug!("Attempting to execute WingetCommand");
 
         match from_value::<WingetCommand>(json_data.clone()) {
             Ok(cmd) => {
-                return cmd.execute(action);
+                return cmd.execute(action, location).await;
             }
             Err(err) => {
-                return Err(format!("Failed to convert data to VcpkgCommand, err: {}", err).into());
+                return Err(InstallError::Parse {
+                    location: location.clone(),
+                    message: format!("Failed to convert data to VcpkgCommand, err: {}", err),
+                });
             }
         }
     }
+
+    fn validate(&self, json_data: &Value, location: &StepLocation, validator: &mut Validator) {
+        validate_step_data::<WingetCommand>(json_data, "WingetCommand", location, validator);
+    }
+
+    fn schema(&self, generator: &mut SchemaGenerator) -> Schema {
+        return generator.subschema_for::<WingetCommand>();
+    }
+
+    fn default_locks(&self) -> Vec<String> {
+        return vec![String::from("winget")];
+    }
 }
@@ -1,10 +1,13 @@
 use super::commands;
 
+use commands::call_command::CallCommandExecutor;
 use commands::common::ActionFn;
 use commands::conditional_command::ConditionalCommandExecutor;
+use commands::define_command::DefineCommandExecutor;
 use commands::delete_reg_key_command::DeleteRegistryValueCommandExecutor;
 use commands::dir_command::DirCommandExecutor;
 use commands::exec_command::ExecCommandExecutor;
+use commands::foreach_command::ForeachCommandExecutor;
 use commands::get_reg_value_command::GetRegistryValueCommandExecutor;
 use commands::include_command::IncludeCommandExecutor;
 use commands::paralel_exec_command::ParalelExecCommandExecutor;
@@ -17,30 +20,55 @@ use commands::winget_command::WingetCommandExecutor;
 pub struct ExecutorFactory {}
 
 impl ExecutorFactory {
-    pub fn build(name: &str) -> Box<dyn ActionFn + Send + Sync> {
+    // every name accepted by build
+    pub fn names() -> Vec<&'static str> {
+        return vec![
+            "exec",
+            "winget",
+            "include",
+            "reg_update",
+            "ps1",
+            "vcpkg",
+            "dir",
+            "set_reg_val",
+            "set_var",
+            "get_reg_val",
+            "if",
+            "delete_reg_key",
+            "paralel",
+            "foreach",
+            "define",
+            "call",
+        ];
+    }
+
+    // returns None for names that don't correspond to any command
+    pub fn build(name: &str) -> Option<Box<dyn ActionFn + Send + Sync>> {
         match name {
             "exec" => {
-                return Box::new(ExecCommandExecutor {});
+                return Some(Box::new(ExecCommandExecutor {}));
             }
             "winget" => {
-                return Box::new(WingetCommandExecutor {});
+                return Some(Box::new(WingetCommandExecutor {}));
             }
             "include" => {
-                return Box::new(IncludeCommandExecutor {});
+                return Some(Box::new(IncludeCommandExecutor {}));
             }
-            "reg_update" => return Box::new(UpdateRegistryCommandExecutor {}),
-            "ps1" => return Box::new(PowershellCommandExecutor {}),
-            "vcpkg" => return Box::new(VcpkgCommandExecutor {}),
-            "dir" => return Box::new(DirCommandExecutor {}),
-            "set_reg_val" => return Box::new(UpdateRegistryCommandExecutor {}),
-            "set_var" => return Box::new(SetVarCommandExecutor {}),
-            "get_reg_val" => return Box::new(GetRegistryValueCommandExecutor {}),
-            "if" => return Box::new(ConditionalCommandExecutor {}),
-            "delete_reg_key" => return Box::new(DeleteRegistryValueCommandExecutor {}),
-            "paralel" => return Box::new(ParalelExecCommandExecutor {}),
+            "reg_update" => return Some(Box::new(UpdateRegistryCommandExecutor {})),
+            "ps1" => return Some(Box::new(PowershellCommandExecutor {})),
+            "vcpkg" => return Some(Box::new(VcpkgCommandExecutor {})),
+            "dir" => return Some(Box::new(DirCommandExecutor {})),
+            "set_reg_val" => return Some(Box::new(UpdateRegistryCommandExecutor {})),
+            "set_var" => return Some(Box::new(SetVarCommandExecutor {})),
+            "get_reg_val" => return Some(Box::new(GetRegistryValueCommandExecutor {})),
+            "if" => return Some(Box::new(ConditionalCommandExecutor {})),
+            "delete_reg_key" => return Some(Box::new(DeleteRegistryValueCommandExecutor {})),
+            "paralel" => return Some(Box::new(ParalelExecCommandExecutor {})),
+            "foreach" => return Some(Box::new(ForeachCommandExecutor {})),
+            "define" => return Some(Box::new(DefineCommandExecutor {})),
+            "call" => return Some(Box::new(CallCommandExecutor {})),
             _ => {
-                // OK to panic here
-                panic!("Failed to create command corresponding to name: {}", name)
+                return None;
             }
         }
     }
@@ -0,0 +1,165 @@
+use super::commands;
+
+use commands::common::{get_install_values, restore_install_values};
+use commands::common::{InstallActionType, StepLocation};
+
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use log::{info, warn};
+
+// checkpoint journal, records every step that finished successfully together with the install
+// values known at that point so an interrupted run can be restarted with --resume
+#[derive(Deserialize, Serialize)]
+struct Journal {
+    #[serde(skip)]
+    path: String,
+
+    #[serde(skip)]
+    persist: bool,
+
+    action: String,
+
+    completed: BTreeSet<String>,
+
+    #[serde(default = "default_values")]
+    values: Value,
+}
+
+fn default_values() -> Value {
+    return json!({});
+}
+
+lazy_static! {
+    static ref JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+}
+
+impl Journal {
+    fn load(path: &str) -> Result<Journal, Box<dyn Error + Send + Sync>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str::<Journal>(&contents) {
+                Ok(journal) => {
+                    return Ok(journal);
+                }
+                Err(err) => {
+                    return Err(
+                        format!("Failed to parse journal file: \"{}\" err: {}", path, err).into(),
+                    );
+                }
+            },
+            Err(err) => {
+                return Err(
+                    format!("Failed to read journal file: \"{}\" err: {}", path, err).into(),
+                );
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if !self.persist {
+            return Ok(());
+        }
+
+        let contents = serde_json::to_string_pretty(&self)?;
+
+        // write to a temporary file first so a crash mid-write never leaves a truncated journal
+        let tmp_path = self.path.clone() + ".tmp";
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        return Ok(());
+    }
+}
+
+pub fn default_journal_path(conf_file: &str) -> String {
+    return conf_file.to_string() + ".journal";
+}
+
+// when resume is false any previous journal is discarded and a new one is started,
+// persist is false for dry runs so nothing gets written to disk
+pub fn open_journal(
+    path: &str,
+    action: &InstallActionType,
+    resume: bool,
+    persist: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut journal = Journal {
+        path: path.to_string(),
+        persist,
+        action: action.name().to_string(),
+        completed: BTreeSet::new(),
+        values: json!({}),
+    };
+
+    if resume && Path::new(path).is_file() {
+        let previous = Journal::load(path)?;
+
+        if previous.action == journal.action {
+            info!(
+                "Resuming from journal \"{}\", {} steps already completed",
+                path,
+                previous.completed.len()
+            );
+            restore_install_values(&previous.values);
+            journal.completed = previous.completed;
+            journal.values = previous.values;
+        } else {
+            warn!(
+                "Journal \"{}\" was recorded for action \"{}\", starting from the beginning",
+                path, previous.action
+            );
+        }
+    }
+
+    journal.save()?;
+
+    *JOURNAL.lock().unwrap() = Some(journal);
+
+    return Ok(());
+}
+
+pub fn is_step_completed(location: &StepLocation) -> bool {
+    match &*JOURNAL.lock().unwrap() {
+        Some(journal) => {
+            return journal.completed.contains(&location.to_string());
+        }
+        None => {
+            return false;
+        }
+    }
+}
+
+pub fn mark_step_completed(location: &StepLocation) {
+    if let Some(journal) = &mut *JOURNAL.lock().unwrap() {
+        journal.completed.insert(location.to_string());
+        journal.values = get_install_values();
+
+        if let Err(err) = journal.save() {
+            warn!(
+                "Failed to update journal \"{}\", resuming might repeat steps, err: {}",
+                journal.path, err
+            );
+        }
+    }
+}
+
+// called once the whole run succeeded, nothing is left to resume
+pub fn close_journal() {
+    if let Some(journal) = JOURNAL.lock().unwrap().take() {
+        if journal.persist {
+            if let Err(err) = fs::remove_file(&journal.path) {
+                warn!(
+                    "Failed to remove journal \"{}\", err: {}",
+                    journal.path, err
+                );
+            }
+        }
+    }
+}
@@ -1,109 +1,470 @@
+mod cli;
 mod commands;
 mod executor_factory;
+mod journal;
 mod rendering;
+mod report;
+mod scheduler;
+mod schema;
+mod step_filter;
+mod step_options;
+mod summary;
+mod validation;
 
+use chrono::{DateTime, Local};
+use clap::Parser;
 use log::{error, info, warn, LevelFilter};
-use simplelog::{Config, TermLogger, TerminalMode};
+use serde_json::{Map, Value};
+use simplelog::{CombinedLogger, Config, SharedLogger, TermLogger, TerminalMode, WriteLogger};
 use std::env;
 use std::error::Error;
 use std::fs;
-use std::path::Path;
+use std::fs::OpenOptions;
+use std::path::{Path, MAIN_SEPARATOR_STR};
 use std::process::exit;
+use std::sync::Arc;
 
-use commands::common::set_install_value;
+// exit codes: 0 success, 1 logger init failed, 2 invalid arguments, 3 to 11 and 13 to 16 see
+// InstallError::exit_code, 12 checkpoint journal could not be opened
+
+use cli::{Cli, CliCommand, ConfigArgs, ListArgs, RunArgs};
 use commands::common::InstallActionType;
+use commands::common::{
+    cancel_run, is_cancelled, is_dry_run, set_dry_run, set_keep_going, set_strict, StepLocation,
+};
+use commands::common::{override_install_value, set_install_value};
+use commands::process_runner::{set_process_runner, FakeProcessRunner};
+use commands::registry_store::{set_registry_store, FileRegistryStore};
+use journal::{close_journal, default_journal_path, open_journal};
 use rendering::install_config;
+use report::{write_json_report, write_junit_report};
+use scheduler::{set_jobs, wake_waiting_steps};
+use schema::config_schema;
+use step_filter::{is_step_selected, set_step_filter};
+use summary::{failed_steps_count, first_failure_exit_code, print_summary};
+use validation::Validator;
+
+fn init_logger(level: LevelFilter, log_file: &Option<String>) -> Result<(), Box<dyn Error>> {
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        level,
+        Config::default(),
+        TerminalMode::Mixed,
+    )];
 
-fn save_cmd(mut args: Vec<String>) -> Result<(), Box<dyn Error + Send + Sync>> {
-    match fs::canonicalize(args[2].clone().as_str()) {
-        Ok(config_absolute_path) => {
-            args[2] = config_absolute_path.to_string_lossy().to_string();
+    if let Some(path) = log_file {
+        // appended so the runs before and after a reboot end up in the same file
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        loggers.push(WriteLogger::new(level, Config::default(), file));
+    }
 
-            let conf_file = args[2].clone();
+    CombinedLogger::init(loggers)?;
+    return Ok(());
+}
 
-            let conf_dir = Path::new(conf_file.as_str()).parent();
-            if conf_dir != None {
-                let conf_dir = conf_dir.unwrap();
-                let conf_dir = conf_dir.to_str().unwrap().to_owned() + "\\";
-                set_install_value("CONF_DIR", conf_dir);
+// command line restarting the run after a reboot, configs get absolute paths since the working
+// directory won't be the same
+fn save_cmd(configs: &[String]) {
+    let mut args: Vec<String> = env::args()
+        .map(|arg| {
+            if configs.contains(&arg) {
+                return canonical_config_path(&arg);
+            }
+            return arg;
+        })
+        .collect();
 
-                let quoted_args: Vec<String> =
-                    args.iter().map(|arg| format!("\"{}\"", arg)).collect();
+    // a command restarted after reboot should continue where it left off
+    if !args.contains(&String::from("--resume")) {
+        args.push(String::from("--resume"));
+    }
 
-                set_install_value("CMD", &quoted_args.join(" "));
+    let quoted_args: Vec<String> = args.iter().map(|arg| format!("\"{}\"", arg)).collect();
 
-                return Ok(());
-            } else {
-                return Err(format!(
-                    "Failed to get parent directory of config file \"{}\"",
-                    conf_file
-                )
-                .into());
-            }
+    set_install_value("CMD", &quoted_args.join(" "));
+}
+
+fn set_conf_dir(conf_file: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match Path::new(conf_file).parent() {
+        Some(conf_dir) => {
+            let conf_dir = conf_dir.to_string_lossy().to_string() + MAIN_SEPARATOR_STR;
+            set_install_value("CONF_DIR", conf_dir);
+
+            return Ok(());
         }
-        Err(err) => {
+        None => {
             return Err(format!(
-                "Failed to get absolute path for file: \"{}\", err: {}",
-                args[2], err
+                "Failed to get parent directory of config file \"{}\"",
+                conf_file
             )
             .into());
         }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    match TermLogger::init(LevelFilter::Trace, Config::default(), TerminalMode::Mixed) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("Failed to init loger err: {}", err);
-            exit(1);
+fn load_vars_file(path: &str) -> Result<Map<String, Value>, Box<dyn Error + Send + Sync>> {
+    let contents = fs::read_to_string(path)?;
+
+    match serde_json::from_str::<Value>(&contents)? {
+        Value::Object(values) => {
+            for (key, value) in values.iter() {
+                let supported = match value {
+                    Value::String(_) | Value::Bool(_) => true,
+                    Value::Number(number) => {
+                        matches!(number.as_u64(), Some(number) if number <= u32::MAX as u64)
+                    }
+                    _ => false,
+                };
+
+                if !supported {
+                    return Err(format!(
+                        "Unsupported value for \"{}\": {}, expected a string, a bool or a non negative integer",
+                        key, value
+                    )
+                    .into());
+                }
+            }
+
+            return Ok(values);
+        }
+        value => {
+            return Err(format!("Expected a json object, found {}", value).into());
+        }
+    }
+}
+
+// values passed with --vars and --set, returns false if a file could not be loaded
+fn set_install_values(args: &ConfigArgs) -> bool {
+    for path in args.var_files.iter() {
+        match load_vars_file(path) {
+            Ok(values) => {
+                for (key, value) in values.iter() {
+                    override_install_value(key, value);
+                }
+            }
+            Err(err) => {
+                error!("Failed to load values from \"{}\", err: {}", path, err);
+                return false;
+            }
+        }
+    }
+
+    for (key, value) in args.values.iter() {
+        override_install_value(key, value);
+    }
+
+    return true;
+}
+
+fn write_recorded_processes(
+    path: &str,
+    fake_runner: &FakeProcessRunner,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let contents = serde_json::to_string_pretty(&fake_runner.invocations())?;
+    fs::write(path, contents)?;
+
+    return Ok(());
+}
+
+// prints the step summary and writes the requested reports, exit_code is the one the run ends with
+fn finish_run(
+    args: &RunArgs,
+    fake_runner: &Option<Arc<FakeProcessRunner>>,
+    conf_files: &[String],
+    action: &InstallActionType,
+    started: &DateTime<Local>,
+    exit_code: i32,
+) {
+    print_summary();
+
+    if let (Some(path), Some(fake_runner)) = (&args.record_processes, fake_runner) {
+        if let Err(err) = write_recorded_processes(path, fake_runner) {
+            error!(
+                "Failed to write recorded processes \"{}\", err: {}",
+                path, err
+            );
+        }
+    }
+
+    if let Some(path) = &args.report {
+        if let Err(err) = write_json_report(path, conf_files, action, started, exit_code) {
+            error!("Failed to write report \"{}\", err: {}", path, err);
+        }
+    }
+
+    if let Some(path) = &args.junit {
+        if let Err(err) = write_junit_report(path, action, started) {
+            error!("Failed to write JUnit report \"{}\", err: {}", path, err);
         }
     }
+}
 
-    let args: Vec<String> = env::args().collect();
+// first Ctrl-C kills the running commands and stops the run, a second one exits right away
+fn handle_ctrl_c() {
+    tokio::spawn(async {
+        loop {
+            if let Err(err) = tokio::signal::ctrl_c().await {
+                warn!("Failed to listen for Ctrl-C, err: {}", err);
+                return;
+            }
 
-    if args.len() > 2 {
-        let action: InstallActionType;
-        match &args[1].as_str() {
-            &"--install" => action = InstallActionType::INSTALL,
-            &"--uninstall" => action = InstallActionType::UNINSTALL,
-            &"--update" => action = InstallActionType::UPDATE,
-            _ => {
-                error!("Invalid option passed {}", &args[1]);
-                // std::io::ErrorKind::Unsoported; to refactor all of this exit codes
-                exit(36);
+            if is_cancelled() {
+                error!("Interrupted again, exiting without waiting for running commands");
+                exit(14);
             }
+
+            warn!("Interrupted, stopping running commands");
+            cancel_run();
+            wake_waiting_steps();
         }
+    });
+}
+
+fn canonical_config_path(conf_file: &String) -> String {
+    match fs::canonicalize(conf_file.as_str()) {
+        Ok(path) => return path.to_string_lossy().to_string(),
+        Err(_) => return conf_file.clone(),
+    }
+}
+
+// statically checks the configs and all of their includes, returns the process exit code
+fn validate(args: ConfigArgs) -> i32 {
+    if !set_install_values(&args) {
+        return 2;
+    }
+
+    let mut validator = Validator::new();
+    for conf_file in args.configs.iter() {
+        let conf_file = canonical_config_path(conf_file);
+        if let Err(err) = set_conf_dir(&conf_file) {
+            warn!("Includes might not resolve, err: {}", err);
+        }
+
+        validator.validate_config(&conf_file, &StepLocation::new(&conf_file));
+    }
+
+    if validator.errors.is_empty() {
+        info!("No problems found in {}", args.configs.join(", "));
+        return 0;
+    }
 
-        match save_cmd(args.clone()) {
+    for err in validator.errors.iter() {
+        error!("{}", err);
+    }
+    error!("{} problems found", validator.errors.len());
+
+    return validator.errors[0].exit_code();
+}
+
+// prints every step the configs contain, marking the ones --only and --skip leave out
+fn list(args: ListArgs) -> i32 {
+    if !set_install_values(&args.config) {
+        return 2;
+    }
+    set_step_filter(&args.filter.only, &args.filter.skip);
+
+    let mut validator = Validator::new();
+    for conf_file in args.config.configs.iter() {
+        let conf_file = canonical_config_path(conf_file);
+        if let Err(err) = set_conf_dir(&conf_file) {
+            warn!("Includes might not resolve, err: {}", err);
+        }
+
+        validator.validate_config(&conf_file, &StepLocation::new(&conf_file));
+    }
+
+    let mut conf_file = String::new();
+    for step in validator.steps.iter() {
+        if step.location.conf_file != conf_file {
+            conf_file = step.location.conf_file.clone();
+            println!("{}", conf_file);
+        }
+
+        let mut details = String::new();
+        if step.does_work {
+            details = serde_json::to_string(&step.json_data)
+                .expect("Failed to convert JSON to string")
+                .chars()
+                .take(100)
+                .collect();
+        }
+
+        let mut status = "";
+        if !is_step_selected(&step.location, &step.command, step.does_work) {
+            status = "  [skipped]";
+        }
+
+        println!(
+            "{}#{}  {}  {}{}",
+            "  ".repeat(step.location.path.len()),
+            step.location.path.join("/"),
+            step.command,
+            details,
+            status
+        );
+    }
+
+    for err in validator.errors.iter() {
+        error!("{}", err);
+    }
+
+    match validator.errors.first() {
+        Some(err) => return err.exit_code(),
+        None => return 0,
+    }
+}
+
+// runs every config one after the other, returns the process exit code
+async fn run(action: InstallActionType, args: RunArgs) -> i32 {
+    set_dry_run(args.dry_run);
+    set_keep_going(args.keep_going);
+    set_strict(args.strict);
+    set_jobs(args.jobs.map(|jobs| jobs as usize));
+    set_step_filter(&args.filter.only, &args.filter.skip);
+
+    let mut fake_runner: Option<Arc<FakeProcessRunner>> = None;
+    if let Some(path) = &args.fake_processes {
+        match FakeProcessRunner::from_file(path) {
+            Ok(runner) => {
+                let runner = Arc::new(runner);
+                set_process_runner(runner.clone());
+                fake_runner = Some(runner);
+            }
             Err(err) => {
-                warn!(
-                    "Failed to save cmd, this might affect installation, err: {}",
-                    err
+                error!(
+                    "Failed to load fake processes from \"{}\", err: {}",
+                    path, err
                 );
+                return 2;
             }
-            _ => {}
         }
+    }
 
-        match install_config(&args[2].clone(), &action).await {
-            Ok(ret) => {
-                if !ret {
-                    error!("One of the commands durring instalation failed, halting execution");
-                    exit(5);
-                }
-            }
+    if let Some(path) = &args.registry {
+        match FileRegistryStore::from_file(path) {
+            Ok(store) => set_registry_store(Arc::new(store)),
             Err(err) => {
-                error!("Failed to install, err: {}", err);
-                exit(5);
+                error!("Failed to load registry from \"{}\", err: {}", path, err);
+                return 2;
             }
         }
+    }
+
+    let conf_files: Vec<String> = args
+        .config
+        .configs
+        .iter()
+        .map(canonical_config_path)
+        .collect();
 
-        // time elapsed here could be nice
-        info!("Instalation finished");
-        exit(0);
+    // a single journal covers all configs, steps are recorded with the config they belong to
+    if let Err(err) = open_journal(
+        &default_journal_path(&conf_files[0]),
+        &action,
+        args.resume,
+        !is_dry_run(),
+    ) {
+        error!("Failed to open checkpoint journal, err: {}", err);
+        return 12;
     }
 
-    error!("Invalid arguments passed! Valid cmd example: \"win_quick_setup --install Conf.json\"");
-    exit(2);
+    // values restored from the journal must not override the ones of the current invocation
+    save_cmd(&args.config.configs);
+    if !set_install_values(&args.config) {
+        return 2;
+    }
+
+    handle_ctrl_c();
+
+    let started = Local::now();
+    let mut result = Ok(());
+    for conf_file in conf_files.iter() {
+        if let Err(err) = set_conf_dir(conf_file) {
+            warn!(
+                "Failed to set CONF_DIR, this might affect installation, err: {}",
+                err
+            );
+        }
+
+        result = install_config(conf_file, &action).await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    match result {
+        Ok(()) => {
+            // failed steps that let the run go on are kept in the journal for --resume
+            if let Some(exit_code) = first_failure_exit_code() {
+                finish_run(
+                    &args,
+                    &fake_runner,
+                    &conf_files,
+                    &action,
+                    &started,
+                    exit_code,
+                );
+                error!(
+                    "{} steps failed, rerun with --resume to retry them",
+                    failed_steps_count()
+                );
+                return exit_code;
+            }
+
+            finish_run(&args, &fake_runner, &conf_files, &action, &started, 0);
+            close_journal();
+        }
+        Err(err) => {
+            finish_run(
+                &args,
+                &fake_runner,
+                &conf_files,
+                &action,
+                &started,
+                err.exit_code(),
+            );
+            error!("{}", err);
+            error!("One of the commands durring instalation failed, halting execution");
+            return err.exit_code();
+        }
+    }
+
+    let elapsed = (Local::now() - started).num_seconds();
+    if is_dry_run() {
+        info!("Dry run finished in {}s, no changes were made", elapsed);
+        return 0;
+    }
+
+    info!("Instalation finished in {}s", elapsed);
+    return 0;
+}
+
+#[tokio::main]
+async fn main() {
+    // invalid arguments exit with 2, --help and --version with 0
+    let cli = Cli::parse();
+
+    if let Err(err) = init_logger(cli.log_level, &cli.log_file) {
+        eprintln!("Failed to init loger err: {}", err);
+        exit(1);
+    }
+
+    match cli.command {
+        CliCommand::Install(args) => exit(run(InstallActionType::INSTALL, args).await),
+        CliCommand::Uninstall(args) => exit(run(InstallActionType::UNINSTALL, args).await),
+        CliCommand::Update(args) => exit(run(InstallActionType::UPDATE, args).await),
+        CliCommand::Plan(mut args) => {
+            args.run.dry_run = true;
+            exit(run(args.action.action(), args.run).await);
+        }
+        CliCommand::Validate(args) => exit(validate(args)),
+        CliCommand::List(args) => exit(list(args)),
+        CliCommand::Schema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&config_schema())
+                    .expect("Failed to convert JSON to string")
+            );
+            exit(0);
+        }
+    }
 }
@@ -1,15 +1,38 @@
 use super::commands;
 
-use commands::common::InstallActionType;
+use commands::common::{
+    delay_unless_cancelled, expand_value, is_cancelled, is_dry_run, is_keep_going, ActionFn,
+    InstallActionType, StepLocation,
+};
+use commands::errors::InstallError;
 
-use serde_json::Value;
-use std::error::Error;
+use chrono::{DateTime, Local};
+use futures::future;
+use log::{info, warn};
+use serde_json::{Map, Value};
 use std::fs::File;
 use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task;
 
 use super::executor_factory::ExecutorFactory;
+use super::journal::{is_step_completed, mark_step_completed};
+use super::scheduler::{
+    acquire_locks, finish_step, jobs_semaphore, register_block, wait_for_needs, wake_waiting_steps,
+    BlockMode, BlockStep, Need,
+};
+use super::step_filter::is_step_selected;
+use super::step_options::{parse_step_options, split_step, StepOptions};
+use super::summary::{
+    failed_steps_count, is_step_failed, is_step_recorded_since, record_failure, record_mark,
+    record_step, StepOutcome,
+};
+
+pub fn load_config_file(conf_file: &String) -> Result<Value, InstallError> {
+    let location = StepLocation::new(conf_file);
 
-fn load_config_file(conf_file: &String) -> Result<Value, Box<dyn Error + Send + Sync>> {
     match File::open(conf_file) {
         Ok(mut file) => {
             let mut contents = String::new();
@@ -20,18 +43,27 @@ fn load_config_file(conf_file: &String) -> Result<Value, Box<dyn Error + Send +
                         return Ok(json_data);
                     }
                     Err(err) => {
-                        return Err(format!("Failed to parse json err: {}", err).into());
+                        return Err(InstallError::Parse {
+                            location,
+                            message: format!("Failed to parse json err: {}", err),
+                        });
                     }
                 },
                 Err(err) => {
-                    return Err(
-                        format!("Failed to read file: \"{}\" err: {}", conf_file, err).into(),
-                    );
+                    return Err(InstallError::Filesystem {
+                        location,
+                        path: conf_file.clone(),
+                        source: err,
+                    });
                 }
             }
         }
         Err(err) => {
-            return Err(format!("Failed to open file: \"{}\", err: {}", conf_file, err).into());
+            return Err(InstallError::Filesystem {
+                location,
+                path: conf_file.clone(),
+                source: err,
+            });
         }
     }
 }
@@ -39,68 +71,444 @@ fn load_config_file(conf_file: &String) -> Result<Value, Box<dyn Error + Send +
 pub async fn install_config(
     conf_file: &String,
     action: &InstallActionType,
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    match load_config_file(&conf_file.clone()) {
-        Ok(json_data) => {
-            return render(&json_data, &action).await;
+) -> Result<(), InstallError> {
+    let json_data = load_config_file(&conf_file.clone())?;
+
+    return render(&json_data, &action, &StepLocation::new(conf_file)).await;
+}
+
+// runs the step again as long as its retry policy allows it
+async fn execute_with_retry(
+    executor: &(dyn ActionFn + Send + Sync),
+    json_data: &Value,
+    action: &InstallActionType,
+    options: &StepOptions,
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    let mut attempt = 1;
+    loop {
+        match executor.execute_command(json_data, action, location).await {
+            Ok(()) => return Ok(()),
+            Err(err) => {
+                if let Some(retry) = &options.retry {
+                    if retry.should_retry(&err, attempt) {
+                        let delay = retry.delay_before_retry(attempt);
+                        warn!(
+                            "{}, retrying in {:.1} seconds, attempt {} of {}",
+                            err,
+                            delay.as_secs_f64(),
+                            attempt + 1,
+                            retry.attempts
+                        );
+
+                        delay_unless_cancelled(delay).await;
+                        if is_cancelled() {
+                            return Err(InstallError::Cancelled {
+                                location: location.clone(),
+                            });
+                        }
+
+                        attempt += 1;
+                        continue;
+                    }
+                }
+
+                return Err(err);
+            }
         }
-        Err(err) => {
-            return Err(format!("Failed to load config err: {}", err).into());
+    }
+}
+
+// records the failure for the summary and decides if the run can go on, step is the command
+// name and data, None when the step is too broken to tell, mark is the record mark taken when
+// the step started
+fn handle_step_failure(
+    err: InstallError,
+    step: Option<(&String, &Value)>,
+    started: DateTime<Local>,
+    mark: usize,
+    continue_on_error: bool,
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    // failures of nested steps are recorded where they happened and only pass through here
+    if !is_step_recorded_since(err.location(), mark) {
+        match step {
+            Some((name, json_data)) if err.location() == location => {
+                record_failure(location, name, expand_value(json_data), started, &err);
+            }
+            _ => {
+                record_failure(err.location(), "-", Value::Null, started, &err);
+            }
         }
     }
+
+    if let InstallError::Cancelled { .. } = err {
+        return Err(err);
+    }
+
+    if continue_on_error || is_keep_going() {
+        warn!("{}, continuing with the next step", err);
+        return Ok(());
+    }
+
+    return Err(err);
 }
 
-pub async fn render(
-    json_data: &Value,
+async fn render_step(
+    object: &Map<String, Value>,
     action: &InstallActionType,
-) -> Result<bool, Box<dyn Error + Send + Sync>> {
-    if let Value::Array(obj) = json_data {
-        for value in obj.iter() {
-            if let Value::Object(object) = value {
-                if object.len() != 1 {
-                    let json_string =
-                        serde_json::to_string(&object).expect("Failed to convert JSON to string");
-
-                    return Err(format!("Invalid instruction found: {}", json_string).into());
-                }
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    // steps already running get stopped by run_process, no new ones are started
+    if is_cancelled() {
+        return Err(InstallError::Cancelled {
+            location: location.clone(),
+        });
+    }
+
+    let started = Local::now();
+    let mark = record_mark();
+
+    let (name, json_data, options);
+    match split_step(object, location) {
+        Ok((step_name, step_data, step_options)) => {
+            name = step_name;
+            json_data = step_data;
+            options = step_options;
+        }
+        Err(err) => {
+            return handle_step_failure(err, None, started, mark, false, location);
+        }
+    }
+
+    let options = match parse_step_options(options, location) {
+        Ok(options) => options,
+        Err(err) => {
+            return handle_step_failure(
+                err,
+                Some((name, json_data)),
+                started,
+                mark,
+                false,
+                location,
+            );
+        }
+    };
 
-                if let Some(first_key) = object.keys().next() {
-                    let executor = ExecutorFactory::build(first_key.as_str());
-                    let future = executor.execute_command(&object[first_key], action);
-
-                    match future.await {
-                        Ok(ret) => {
-                            if !ret {
-                                let json_string = serde_json::to_string(&object)
-                                    .expect("Failed to convert JSON to string");
-                                println!("Command failed: {}", json_string);
-                                return Ok(false);
-                            }
+    match ExecutorFactory::build(name.as_str()) {
+        Some(executor) => {
+            let apply_only = !executor.runs_nested_steps()
+                && !executor.sets_install_values()
+                && !executor.is_definition();
+            if !is_step_selected(location, name, apply_only) {
+                info!("Skipping step {}, filtered out", location);
+                record_step(
+                    location,
+                    name,
+                    expand_value(json_data),
+                    started,
+                    StepOutcome::Skipped(String::from("filtered out")),
+                );
+                return Ok(());
+            }
+
+            if !executor.is_definition() && is_step_completed(location) {
+                info!("Skipping step {}, already completed", location);
+                record_step(
+                    location,
+                    name,
+                    expand_value(json_data),
+                    started,
+                    StepOutcome::Skipped(String::from("already completed")),
+                );
+                return Ok(());
+            }
+
+            // blocks only wait for their nested steps, holding locks or a job would starve them
+            let mut locks = executor.default_locks();
+            locks.extend(options.locks.iter().cloned());
+            let _locks = match executor.runs_nested_steps() {
+                true => Vec::new(),
+                false => acquire_locks(&locks).await,
+            };
+
+            let jobs = jobs_semaphore();
+            let _job = match &jobs {
+                Some(jobs) if !executor.runs_nested_steps() => Some(jobs.acquire().await),
+                _ => None,
+            };
+
+            let failures_before = failed_steps_count();
+
+            match execute_with_retry(&*executor, json_data, action, &options, location).await {
+                Ok(()) => {
+                    if executor.runs_nested_steps() {
+                        // nested steps that failed without stopping the run must run again on resume
+                        if failed_steps_count() == failures_before {
+                            mark_step_completed(location);
                         }
-                        Err(err) => {
-                            let json_string = serde_json::to_string(&object)
-                                .expect("Failed to convert JSON to string");
-                            panic!(
-                                "Failed to run command: \"{}\", err: \"{}\"",
-                                json_string, err
-                            );
+                    } else {
+                        // steps skipped by their guards record themselves, a record left by an
+                        // earlier attempt gets replaced, dry runs only printed what would run
+                        if !is_step_recorded_since(location, mark) {
+                            let outcome = match is_dry_run() {
+                                true => StepOutcome::Skipped(String::from("dry run")),
+                                false => StepOutcome::Succeeded,
+                            };
+                            record_step(location, name, expand_value(json_data), started, outcome);
                         }
+                        mark_step_completed(location);
                     }
-                } else {
-                    let json_string =
-                        serde_json::to_string(&object).expect("Failed to convert JSON to string");
-                    panic!(
-                        "Failed to found matching instruction for json: {}",
-                        json_string
+
+                    return Ok(());
+                }
+                Err(err) => {
+                    return handle_step_failure(
+                        err,
+                        Some((name, json_data)),
+                        started,
+                        mark,
+                        options.continue_on_error,
+                        location,
                     );
                 }
             }
         }
-    } else {
-        let json_string =
-            serde_json::to_string(&json_data).expect("Failed to convert JSON to string");
-        panic!("Invalid syntax, comands are supposed to be contained into an array of objects, found {}", json_string);
+        None => {
+            let err = InstallError::UnknownCommand {
+                location: location.clone(),
+                name: name.clone(),
+            };
+            return handle_step_failure(
+                err,
+                Some((name, json_data)),
+                started,
+                mark,
+                options.continue_on_error,
+                location,
+            );
+        }
     }
+}
 
-    return Ok(true);
+// waits for what the step needs and for a free slot of the block before running it, aborted is
+// set once a step of a fail fast block failed
+async fn render_block_step(
+    object: &Map<String, Value>,
+    action: &InstallActionType,
+    location: &StepLocation,
+    needs: &[Need],
+    concurrency: Option<&Semaphore>,
+    fail_fast: bool,
+    aborted: &AtomicBool,
+) -> Result<(), InstallError> {
+    let started = Local::now();
+    let mark = record_mark();
+    let step = split_step(object, location)
+        .ok()
+        .map(|(name, json_data, _)| (name, json_data));
+
+    let result = match wait_for_needs(needs, location, aborted).await {
+        Ok(None) => {
+            let _slot = match concurrency {
+                Some(concurrency) => Some(concurrency.acquire().await),
+                None => None,
+            };
+
+            if aborted.load(Ordering::SeqCst) {
+                let reason = match is_cancelled() {
+                    true => String::from("run cancelled"),
+                    false => String::from("a step of the block failed"),
+                };
+                info!("Skipping step {}, {}", location, reason);
+                if let Some((name, json_data)) = step {
+                    record_step(
+                        location,
+                        name,
+                        expand_value(json_data),
+                        started,
+                        StepOutcome::Skipped(reason),
+                    );
+                }
+                finish_step(location, false);
+                return Ok(());
+            }
+
+            let result = render_step(object, action, location).await;
+            // set before the slot is released, steps waiting for it must not start
+            if result.is_err() && (fail_fast || is_cancelled()) {
+                aborted.store(true, Ordering::SeqCst);
+                wake_waiting_steps();
+            }
+            result
+        }
+        Ok(Some(failed)) => {
+            info!("Skipping step {}, needed step {} failed", location, failed);
+            if let Some((name, json_data)) = step {
+                record_step(
+                    location,
+                    name,
+                    expand_value(json_data),
+                    started,
+                    StepOutcome::Skipped(format!("needed step {} failed", failed)),
+                );
+            }
+            finish_step(location, false);
+            return Ok(());
+        }
+        Err(err) => handle_step_failure(err, step, started, mark, false, location),
+    };
+
+    finish_step(location, result.is_ok() && !is_step_failed(location));
+    return result;
+}
+
+// id and needs of every step, steps too broken to tell fail once they run
+pub fn block_steps(steps: &[(StepLocation, Map<String, Value>)]) -> Vec<BlockStep> {
+    return steps
+        .iter()
+        .map(|(location, object)| {
+            let options = split_step(object, location)
+                .ok()
+                .and_then(|(_, _, options)| parse_step_options(options, location).ok());
+
+            return BlockStep {
+                location: location.clone(),
+                id: options.as_ref().and_then(|options| options.id.clone()),
+                needs: options.and_then(|options| options.needs),
+            };
+        })
+        .collect();
+}
+
+// location identifies the block itself, every step inside gets its index appended. Steps run one
+// after the other unless one of them has needs or the block is parallel, then every step starts
+// as soon as what it waits for is done
+pub async fn render_block(
+    json_data: &Value,
+    action: &InstallActionType,
+    location: &StepLocation,
+    mode: BlockMode,
+) -> Result<(), InstallError> {
+    let obj = match json_data {
+        Value::Array(obj) => obj,
+        _ => {
+            let json_string =
+                serde_json::to_string(&json_data).expect("Failed to convert JSON to string");
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: format!("Invalid syntax, comands are supposed to be contained into an array of objects, found {}", json_string),
+            });
+        }
+    };
+
+    let steps: Vec<(StepLocation, Map<String, Value>)> = obj
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, value)| match value {
+            Value::Object(object) => Some((location.child(idx), object.clone())),
+            _ => None,
+        })
+        .collect();
+
+    let block_steps = block_steps(&steps);
+    let scheduled = mode.parallel || block_steps.iter().any(|step| step.needs.is_some());
+    let block_needs = register_block(location, &block_steps, mode)?;
+
+    if !scheduled {
+        // steps after a failed one are still finished as skipped, steps of other blocks may
+        // need them
+        let aborted = AtomicBool::new(false);
+        let mut first_err = None;
+        for ((step_location, object), needs) in steps.iter().zip(block_needs.iter()) {
+            let result =
+                render_block_step(object, action, step_location, needs, None, true, &aborted).await;
+            if let Err(err) = result {
+                if first_err.is_none() {
+                    first_err = Some(err);
+                }
+            }
+        }
+
+        match first_err {
+            Some(err) => return Err(err),
+            None => return Ok(()),
+        }
+    }
+
+    let concurrency = mode
+        .max_concurrency
+        .map(|max_concurrency| Arc::new(Semaphore::new(max_concurrency)));
+    let aborted = Arc::new(AtomicBool::new(false));
+    let mut tasks = vec![];
+    for ((step_location, object), needs) in steps.into_iter().zip(block_needs) {
+        let cpy_action = action.clone();
+        let cpy_concurrency = concurrency.clone();
+        let cpy_aborted = aborted.clone();
+        tasks.push(task::spawn(async move {
+            return render_block_step(
+                &object,
+                &cpy_action,
+                &step_location,
+                &needs,
+                cpy_concurrency.as_deref(),
+                mode.fail_fast,
+                &cpy_aborted,
+            )
+            .await;
+        }));
+    }
+
+    // every task is waited for, none is left running once the block returns
+    let mut first_err = None;
+    let mut futures = tasks;
+    while !futures.is_empty() {
+        let (item_resolved, _idx, remaining_futures) = future::select_all(futures).await;
+        futures = remaining_futures;
+
+        let result = match item_resolved {
+            Ok(result) => result,
+            Err(err) => Err(InstallError::Task {
+                location: location.clone(),
+                message: format!("Step task failed with error: {:?}", err),
+            }),
+        };
+
+        if let Err(err) = result {
+            // steps not started yet give up, running ones get to finish
+            if mode.fail_fast || is_cancelled() {
+                aborted.store(true, Ordering::SeqCst);
+                wake_waiting_steps();
+            }
+            if first_err.is_none() {
+                first_err = Some(err);
+            }
+        }
+    }
+
+    match first_err {
+        Some(err) => return Err(err),
+        None => return Ok(()),
+    }
+}
+
+pub async fn render(
+    json_data: &Value,
+    action: &InstallActionType,
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    return render_block(json_data, action, location, BlockMode::SEQUENTIAL).await;
+}
+
+// block run more than once, ex: the steps of a call or of a foreach item, see BlockMode::scoped
+pub async fn render_scoped(
+    json_data: &Value,
+    action: &InstallActionType,
+    location: &StepLocation,
+) -> Result<(), InstallError> {
+    let mode = BlockMode {
+        scoped: true,
+        ..BlockMode::SEQUENTIAL
+    };
+    return render_block(json_data, action, location, mode).await;
 }
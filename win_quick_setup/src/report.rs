@@ -0,0 +1,194 @@
+use super::commands;
+
+use commands::common::{is_dry_run, InstallActionType};
+
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::fs;
+
+use super::summary::{step_records, StepOutcome, StepRecord};
+
+fn duration_secs(started: &DateTime<Local>, finished: &DateTime<Local>) -> f64 {
+    return (*finished - *started).num_milliseconds() as f64 / 1000.0;
+}
+
+fn outcome_name(outcome: &StepOutcome) -> &'static str {
+    match outcome {
+        StepOutcome::Succeeded => "succeeded",
+        StepOutcome::Failed(..) => "failed",
+        StepOutcome::Skipped(_) => "skipped",
+    }
+}
+
+fn step_report(record: &StepRecord) -> Value {
+    let mut error = Value::Null;
+    let mut skip_reason = Value::Null;
+    match &record.outcome {
+        StepOutcome::Failed(message, _) => error = Value::String(message.clone()),
+        StepOutcome::Skipped(reason) => skip_reason = Value::String(reason.clone()),
+        StepOutcome::Succeeded => {}
+    }
+
+    return json!({
+        "config_file": record.location.conf_file,
+        "path": record.location.path.join("/"),
+        "command": record.command,
+        "parameters": record.parameters,
+        "started": record.started.to_rfc3339(),
+        "finished": record.finished.to_rfc3339(),
+        "duration_secs": duration_secs(&record.started, &record.finished),
+        "exit_code": record.exit_code,
+        "outcome": outcome_name(&record.outcome),
+        "error": error,
+        "skip_reason": skip_reason,
+    });
+}
+
+// every recorded step of the run together with how the run ended, exit_code is the one of
+// win_quick_setup itself
+pub fn write_json_report(
+    path: &str,
+    conf_files: &[String],
+    action: &InstallActionType,
+    started: &DateTime<Local>,
+    exit_code: i32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let finished = Local::now();
+    let steps: Vec<Value> = step_records().iter().map(step_report).collect();
+
+    let report = json!({
+        "config_files": conf_files,
+        "action": action.name(),
+        "dry_run": is_dry_run(),
+        "started": started.to_rfc3339(),
+        "finished": finished.to_rfc3339(),
+        "duration_secs": duration_secs(started, &finished),
+        "exit_code": exit_code,
+        "steps": steps,
+    });
+
+    fs::write(path, serde_json::to_string_pretty(&report)?)?;
+    return Ok(());
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // control characters other than whitespace are not allowed in xml 1.0
+            c if c.is_control() && c != '\n' && c != '\r' && c != '\t' => {}
+            c => escaped.push(c),
+        }
+    }
+
+    return escaped;
+}
+
+// one test suite per config file and one test case per step, for CI systems and dashboards
+// that understand JUnit results
+pub fn write_junit_report(
+    path: &str,
+    action: &InstallActionType,
+    started: &DateTime<Local>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let finished = Local::now();
+    let records = step_records();
+
+    // config files in the order they were first run
+    let mut conf_files: Vec<&String> = Vec::new();
+    for record in records.iter() {
+        if !conf_files.contains(&&record.location.conf_file) {
+            conf_files.push(&record.location.conf_file);
+        }
+    }
+
+    let count = |records: &Vec<&StepRecord>, outcome: &str| {
+        records
+            .iter()
+            .filter(|record| outcome_name(&record.outcome) == outcome)
+            .count()
+    };
+
+    let all_records: Vec<&StepRecord> = records.iter().collect();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"win_quick_setup {}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        action.name(),
+        all_records.len(),
+        count(&all_records, "failed"),
+        count(&all_records, "skipped"),
+        duration_secs(started, &finished)
+    ));
+
+    for conf_file in conf_files {
+        let suite_records: Vec<&StepRecord> = records
+            .iter()
+            .filter(|record| &record.location.conf_file == conf_file)
+            .collect();
+
+        let suite_started = suite_records[0].started;
+        let suite_finished = suite_records
+            .iter()
+            .map(|record| record.finished)
+            .max()
+            .unwrap_or(suite_started);
+
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\" timestamp=\"{}\">\n",
+            escape_xml(conf_file),
+            suite_records.len(),
+            count(&suite_records, "failed"),
+            count(&suite_records, "skipped"),
+            duration_secs(&suite_started, &suite_finished),
+            suite_started.format("%Y-%m-%dT%H:%M:%S")
+        ));
+
+        for record in suite_records {
+            xml.push_str(&format!(
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                escape_xml(conf_file),
+                escape_xml(&format!(
+                    "{} {}",
+                    record.location.path.join("/"),
+                    record.command
+                )),
+                duration_secs(&record.started, &record.finished)
+            ));
+
+            match &record.outcome {
+                StepOutcome::Succeeded => {
+                    xml.push_str("/>\n");
+                }
+                StepOutcome::Failed(message, _) => {
+                    xml.push_str(">\n");
+                    xml.push_str(&format!(
+                        "      <failure message=\"{}\">{}</failure>\n",
+                        escape_xml(message),
+                        escape_xml(&serde_json::to_string_pretty(&record.parameters)?)
+                    ));
+                    xml.push_str("    </testcase>\n");
+                }
+                StepOutcome::Skipped(reason) => {
+                    xml.push_str(&format!(
+                        ">\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                        escape_xml(reason)
+                    ));
+                }
+            }
+        }
+
+        xml.push_str("  </testsuite>\n");
+    }
+
+    xml.push_str("</testsuites>\n");
+
+    fs::write(path, xml)?;
+    return Ok(());
+}
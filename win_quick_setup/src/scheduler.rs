@@ -0,0 +1,479 @@
+use super::commands;
+
+use commands::common::{is_cancelled, StepLocation};
+use commands::errors::InstallError;
+
+use futures::channel::oneshot;
+use lazy_static::lazy_static;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, Semaphore};
+
+// id and needs of a step, read before any step of its block starts
+pub struct BlockStep {
+    pub location: StepLocation,
+    pub id: Option<String>,
+    pub needs: Option<Vec<String>>,
+}
+
+// how the steps of a block get scheduled
+#[derive(Clone, Copy)]
+pub struct BlockMode {
+    // steps without needs don't wait for the one placed before them
+    pub parallel: bool,
+    // most steps of the block running at the same time, None for no limit
+    pub max_concurrency: Option<usize>,
+    // no step starts once one failed, the running ones still finish
+    pub fail_fast: bool,
+    // ids of the steps are only known inside this run of the block and the blocks nested in it,
+    // ex: the steps of a call or of a foreach item, which run more than once
+    pub scoped: bool,
+}
+
+impl BlockMode {
+    pub const SEQUENTIAL: BlockMode = BlockMode {
+        parallel: false,
+        max_concurrency: None,
+        fail_fast: true,
+        scoped: false,
+    };
+}
+
+// something a step waits for before starting
+#[derive(Clone)]
+pub enum Need {
+    // step of the same block or registered before it, explicit when named in needs, a failed
+    // explicit need skips the step
+    Step {
+        key: String,
+        explicit: bool,
+    },
+    // id not registered when the block started, it has to show up while the steps placed before
+    // this one run, ex: a step of a config included right before
+    Id {
+        id: String,
+        scopes: Vec<String>,
+        earlier: Vec<String>,
+    },
+}
+
+struct StepNode {
+    // id or location, used in messages
+    label: String,
+    // None until the step finished, then whether it succeeded
+    succeeded: Option<bool>,
+}
+
+// every step registered during the run, keyed by location
+struct StepGraph {
+    // step keys by scope and id, the scope being the key of a scoped block or "" for the whole run
+    ids: HashMap<(String, String), String>,
+    // keys of the scoped blocks, see BlockMode
+    scopes: HashSet<String>,
+    nodes: HashMap<String, StepNode>,
+    // steps every pending step waits for, blocks also wait for their nested steps
+    edges: HashMap<String, Vec<String>>,
+    // include step that loaded a config, keyed by config file
+    includes: HashMap<String, StepLocation>,
+    // woken up whenever a step gets registered or finishes
+    watchers: Vec<oneshot::Sender<()>>,
+}
+
+lazy_static! {
+    static ref STEP_GRAPH: Mutex<StepGraph> = Mutex::new(StepGraph {
+        ids: HashMap::new(),
+        scopes: HashSet::new(),
+        nodes: HashMap::new(),
+        edges: HashMap::new(),
+        includes: HashMap::new(),
+        watchers: Vec::new(),
+    });
+    static ref JOBS: Mutex<Option<Arc<Semaphore>>> = Mutex::new(None);
+    static ref LOCKS: Mutex<HashMap<String, Arc<AsyncMutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+// limits how many steps run a command at the same time, None for no limit
+pub fn set_jobs(jobs: Option<usize>) {
+    *JOBS.lock().unwrap() = jobs.map(|jobs| Arc::new(Semaphore::new(jobs)));
+}
+
+pub fn jobs_semaphore() -> Option<Arc<Semaphore>> {
+    return JOBS.lock().unwrap().clone();
+}
+
+// held while a step runs its command, names are taken in order so two steps never wait for
+// each other
+pub async fn acquire_locks(names: &[String]) -> Vec<OwnedMutexGuard<()>> {
+    let mut names = names.to_vec();
+    names.sort();
+    names.dedup();
+
+    let mut guards = Vec::new();
+    for name in names.iter() {
+        let lock = LOCKS
+            .lock()
+            .unwrap()
+            .entry(name.clone())
+            .or_insert_with(|| Arc::new(AsyncMutex::new(())))
+            .clone();
+        guards.push(lock.lock_owned().await);
+    }
+
+    return guards;
+}
+
+// path of keys going from start back to start, edges of finished steps are gone so only
+// pending steps can be part of it
+pub fn find_cycle(edges: &HashMap<String, Vec<String>>, start: &str) -> Option<Vec<String>> {
+    let mut path = vec![start.to_string()];
+    let mut visited = HashSet::new();
+
+    if find_path(edges, start, start, &mut path, &mut visited) {
+        return Some(path);
+    }
+
+    return None;
+}
+
+fn find_path(
+    edges: &HashMap<String, Vec<String>>,
+    from: &str,
+    to: &str,
+    path: &mut Vec<String>,
+    visited: &mut HashSet<String>,
+) -> bool {
+    if let Some(targets) = edges.get(from) {
+        for target in targets.iter() {
+            path.push(target.clone());
+            if target == to {
+                return true;
+            }
+
+            if visited.insert(target.clone()) && find_path(edges, target, to, path, visited) {
+                return true;
+            }
+            path.pop();
+        }
+    }
+
+    return false;
+}
+
+impl StepGraph {
+    fn label(&self, key: &str) -> String {
+        match self.nodes.get(key) {
+            Some(node) => return node.label.clone(),
+            None => return key.to_string(),
+        }
+    }
+
+    // step running the block, nested blocks extend the location of their step while included
+    // configs start a new one
+    fn parent_key(&self, block: &StepLocation) -> Option<String> {
+        let mut location = block.clone();
+        while location.path.pop().is_some() {
+            if self.nodes.contains_key(&location.to_string()) {
+                return Some(location.to_string());
+            }
+        }
+
+        return self
+            .includes
+            .get(&block.conf_file)
+            .map(|include| include.to_string());
+    }
+
+    // scopes whose ids a step at the location can see, innermost first and "" last, configs
+    // included by a step belong to the scopes of that step
+    fn scope_chain(&self, location: &StepLocation) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut location = location.clone();
+        let mut visited_configs = HashSet::new();
+        loop {
+            let key = location.to_string();
+            if self.scopes.contains(&key) {
+                chain.push(key);
+            }
+
+            if location.path.pop().is_none() {
+                if !visited_configs.insert(location.conf_file.clone()) {
+                    break;
+                }
+                match self.includes.get(&location.conf_file) {
+                    Some(include) => location = include.clone(),
+                    None => break,
+                }
+            }
+        }
+
+        chain.push(String::new());
+        return chain;
+    }
+
+    fn find_id(&self, scopes: &[String], id: &str) -> Option<String> {
+        return scopes
+            .iter()
+            .find_map(|scope| self.ids.get(&(scope.clone(), id.to_string())).cloned());
+    }
+
+    fn add_edge(
+        &mut self,
+        from: &str,
+        to: &str,
+        location: &StepLocation,
+    ) -> Result<(), InstallError> {
+        self.edges
+            .entry(from.to_string())
+            .or_default()
+            .push(to.to_string());
+
+        return self.check_cycle(from, location);
+    }
+
+    fn check_cycle(&self, key: &str, location: &StepLocation) -> Result<(), InstallError> {
+        if let Some(cycle) = find_cycle(&self.edges, key) {
+            let labels: Vec<String> = cycle.iter().map(|key| self.label(key)).collect();
+
+            return Err(InstallError::Dependency {
+                location: location.clone(),
+                message: format!("Step dependency cycle: {}", labels.join(" -> ")),
+            });
+        }
+
+        return Ok(());
+    }
+
+    fn notify(&mut self) {
+        for watcher in self.watchers.drain(..) {
+            let _ = watcher.send(());
+        }
+    }
+}
+
+pub fn link_include(conf_file: &str, location: &StepLocation) {
+    STEP_GRAPH
+        .lock()
+        .unwrap()
+        .includes
+        .insert(conf_file.to_string(), location.clone());
+}
+
+// registers the steps of a block before any of them runs and returns what each one waits for,
+// steps without needs wait for the one placed before them unless the block is parallel
+pub fn register_block(
+    block: &StepLocation,
+    steps: &[BlockStep],
+    mode: BlockMode,
+) -> Result<Vec<Vec<Need>>, InstallError> {
+    let mut graph = STEP_GRAPH.lock().unwrap();
+    let keys: Vec<String> = steps.iter().map(|step| step.location.to_string()).collect();
+
+    if mode.scoped {
+        graph.scopes.insert(block.to_string());
+    }
+    let scopes = graph.scope_chain(block);
+
+    // ids come first, needs can name steps placed after them
+    for (step, key) in steps.iter().zip(keys.iter()) {
+        if let Some(id) = &step.id {
+            let scoped_id = (scopes[0].clone(), id.clone());
+            if let Some(other) = graph.ids.get(&scoped_id) {
+                // blocks run again by a retry register the same steps
+                if other != key {
+                    return Err(InstallError::Dependency {
+                        location: step.location.clone(),
+                        message: format!("Duplicate step id \"{}\", already used by {}", id, other),
+                    });
+                }
+            }
+            graph.ids.insert(scoped_id, key.clone());
+        }
+
+        graph.nodes.insert(
+            key.clone(),
+            StepNode {
+                label: step.id.clone().unwrap_or_else(|| key.clone()),
+                succeeded: None,
+            },
+        );
+        graph.edges.remove(key);
+    }
+
+    if let Some(parent) = graph.parent_key(block) {
+        graph.edges.entry(parent).or_default().extend(keys.clone());
+    }
+
+    let mut block_needs = Vec::new();
+    for (idx, step) in steps.iter().enumerate() {
+        let mut needs = Vec::new();
+        match &step.needs {
+            Some(names) => {
+                for name in names.iter() {
+                    match graph.find_id(&scopes, name) {
+                        Some(key) => needs.push(Need::Step {
+                            key,
+                            explicit: true,
+                        }),
+                        None => needs.push(Need::Id {
+                            id: name.clone(),
+                            scopes: scopes.clone(),
+                            earlier: keys[..idx].to_vec(),
+                        }),
+                    }
+                }
+            }
+            None if !mode.parallel && idx > 0 => needs.push(Need::Step {
+                key: keys[idx - 1].clone(),
+                explicit: false,
+            }),
+            None => {}
+        }
+
+        for need in needs.iter() {
+            if let Need::Step { key, .. } = need {
+                graph
+                    .edges
+                    .entry(keys[idx].clone())
+                    .or_default()
+                    .push(key.clone());
+            }
+        }
+        block_needs.push(needs);
+    }
+
+    // reported before anything in the block starts
+    for (step, key) in steps.iter().zip(keys.iter()) {
+        graph.check_cycle(key, &step.location)?;
+    }
+
+    graph.notify();
+    return Ok(block_needs);
+}
+
+impl StepGraph {
+    // resolves once a step gets registered or finishes
+    fn watch(&mut self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.watchers.push(sender);
+
+        return receiver;
+    }
+}
+
+// wakes up the steps waiting for their needs, ex: once their block gave up or the run got
+// cancelled
+pub fn wake_waiting_steps() {
+    STEP_GRAPH.lock().unwrap().notify();
+}
+
+// true once the step should give up waiting, the block skips it when aborted
+fn stop_waiting(location: &StepLocation, aborted: &AtomicBool) -> Result<bool, InstallError> {
+    if is_cancelled() {
+        return Err(InstallError::Cancelled {
+            location: location.clone(),
+        });
+    }
+
+    return Ok(aborted.load(Ordering::SeqCst));
+}
+
+// key of the step with the id, waiting for it to be registered by one of the earlier steps,
+// None once the block got aborted
+async fn resolve_id(
+    id: &str,
+    scopes: &[String],
+    earlier: &[String],
+    location: &StepLocation,
+    aborted: &AtomicBool,
+) -> Result<Option<String>, InstallError> {
+    loop {
+        let changed = {
+            let mut graph = STEP_GRAPH.lock().unwrap();
+            if let Some(key) = graph.find_id(scopes, id) {
+                graph.add_edge(&location.to_string(), &key, location)?;
+                return Ok(Some(key));
+            }
+
+            let pending = earlier
+                .iter()
+                .any(|key| matches!(graph.nodes.get(key), Some(node) if node.succeeded.is_none()));
+            if !pending {
+                return Err(InstallError::Dependency {
+                    location: location.clone(),
+                    message: format!(
+                        "Unknown step id \"{}\", steps can only need steps of their block or placed before them",
+                        id
+                    ),
+                });
+            }
+
+            if stop_waiting(location, aborted)? {
+                return Ok(None);
+            }
+            graph.watch()
+        };
+
+        let _ = changed.await;
+    }
+}
+
+// waits for every need of the step, returns the label of a failed explicit need, stops waiting
+// once the block of the step got aborted
+pub async fn wait_for_needs(
+    needs: &[Need],
+    location: &StepLocation,
+    aborted: &AtomicBool,
+) -> Result<Option<String>, InstallError> {
+    for need in needs.iter() {
+        let (key, explicit) = match need {
+            Need::Step { key, explicit } => (key.clone(), *explicit),
+            Need::Id {
+                id,
+                scopes,
+                earlier,
+            } => match resolve_id(id, scopes, earlier, location, aborted).await? {
+                Some(key) => (key, true),
+                None => return Ok(None),
+            },
+        };
+
+        loop {
+            let changed = {
+                let mut graph = STEP_GRAPH.lock().unwrap();
+                match graph.nodes.get(&key).and_then(|node| node.succeeded) {
+                    Some(true) => break,
+                    // steps placed after a failed one still run with --keep-going
+                    Some(false) if !explicit => break,
+                    Some(false) => return Ok(Some(graph.label(&key))),
+                    None => {
+                        if stop_waiting(location, aborted)? {
+                            return Ok(None);
+                        }
+                        graph.watch()
+                    }
+                }
+            };
+
+            let _ = changed.await;
+        }
+    }
+
+    return Ok(None);
+}
+
+// the first call decides, later ones are ignored
+pub fn finish_step(location: &StepLocation, succeeded: bool) {
+    let mut graph = STEP_GRAPH.lock().unwrap();
+    let key = location.to_string();
+
+    if let Some(node) = graph.nodes.get_mut(&key) {
+        if node.succeeded.is_some() {
+            return;
+        }
+        node.succeeded = Some(succeeded);
+    }
+
+    graph.edges.remove(&key);
+    graph.notify();
+}
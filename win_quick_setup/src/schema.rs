@@ -0,0 +1,117 @@
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use super::executor_factory::ExecutorFactory;
+use super::step_options::StepOptions;
+
+const STEPS_DEFINITION: &str = "Steps";
+
+// used by commands that contain nested blocks of steps
+pub fn steps_schema(_generator: &mut SchemaGenerator) -> Schema {
+    return Schema::new_ref(format!("#/definitions/{}", STEPS_DEFINITION));
+}
+
+// json schema of the config format, an array of steps each being an object with a single
+// command key and optional step options, plain strings are accepted as comments
+pub fn config_schema() -> Value {
+    let mut generator = SchemaSettings::draft07().into_generator();
+
+    let mut option_properties = Map::new();
+    if let Some(object) = StepOptions::json_schema(&mut generator)
+        .into_object()
+        .object
+    {
+        for (name, schema) in object.properties.iter() {
+            option_properties.insert(name.clone(), json!(schema));
+        }
+    }
+
+    let mut steps: Vec<Value> = Vec::new();
+    for name in ExecutorFactory::names() {
+        if let Some(executor) = ExecutorFactory::build(name) {
+            let mut properties = option_properties.clone();
+            properties.insert(name.to_string(), json!(executor.schema(&mut generator)));
+
+            steps.push(json!({
+                "type": "object",
+                "properties": properties,
+                "required": [name],
+                "additionalProperties": false,
+            }));
+        }
+    }
+
+    steps.push(json!({
+        "type": "string",
+        "description": "Comment, ignored when running the config.",
+    }));
+
+    // commands reject fields they don't know, see validation::validate_step_data, flattened fields
+    // like the capture and guard ones are part of the properties by now
+    let mut definitions = Map::new();
+    for (name, schema) in generator.definitions().iter() {
+        let mut schema = json!(schema);
+        if schema["type"] == "object" && schema.get("additionalProperties").is_none() {
+            schema["additionalProperties"] = json!(false);
+        }
+        definitions.insert(name.clone(), schema);
+    }
+    definitions.insert(
+        String::from(STEPS_DEFINITION),
+        json!({
+            "type": "array",
+            "items": { "anyOf": steps },
+        }),
+    );
+
+    return json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "win_quick_setup config",
+        "$ref": format!("#/definitions/{}", STEPS_DEFINITION),
+        "definitions": definitions,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_reject_unknown_fields() {
+        let schema = config_schema();
+        let definitions = schema["definitions"].as_object().unwrap();
+
+        for (name, definition) in definitions.iter() {
+            if definition["type"] == "object" {
+                assert_eq!(definition["additionalProperties"], false, "{}", name);
+            }
+        }
+
+        // flattened capture and guard fields are still allowed
+        let exec = &definitions["ExecCommand"]["properties"];
+        for field in [
+            "install_run",
+            "capture_stdout",
+            "creates",
+            "only_if",
+            "unless",
+        ] {
+            assert!(exec.get(field).is_some(), "{}", field);
+        }
+    }
+
+    #[test]
+    fn steps_reject_unknown_keys() {
+        let schema = config_schema();
+        for step in schema["definitions"]["Steps"]["items"]["anyOf"]
+            .as_array()
+            .unwrap()
+        {
+            if step["type"] == "object" {
+                assert_eq!(step["additionalProperties"], false);
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use super::commands;
+
+use commands::common::StepLocation;
+
+use lazy_static::lazy_static;
+use std::path::Path;
+use std::sync::Mutex;
+
+// --only and --skip patterns, see FilterArgs
+struct StepFilter {
+    only: Vec<String>,
+    skip: Vec<String>,
+}
+
+lazy_static! {
+    static ref STEP_FILTER: Mutex<StepFilter> = Mutex::new(StepFilter {
+        only: Vec::new(),
+        skip: Vec::new(),
+    });
+}
+
+pub fn set_step_filter(only: &[String], skip: &[String]) {
+    let mut filter = STEP_FILTER.lock().unwrap();
+    filter.only = only.to_vec();
+    filter.skip = skip.to_vec();
+}
+
+// a pattern is a command name, a config file name or a step location relative to the config
+// file name, locations also match every step nested inside, ex: "General.json#3" matches
+// "General.json#3/run/0"
+fn matches(pattern: &str, location: &StepLocation, command: &str) -> bool {
+    if pattern == command {
+        return true;
+    }
+
+    let file_name = match Path::new(&location.conf_file).file_name() {
+        Some(file_name) => file_name.to_string_lossy().to_string(),
+        None => location.conf_file.clone(),
+    };
+
+    if pattern == file_name {
+        return true;
+    }
+
+    let step = format!("{}#{}", file_name, location.path.join("/"));
+    return step == pattern || step.starts_with(&format!("{}/", pattern));
+}
+
+// only is applied to steps doing actual work, blocks of steps are always entered so their
+// nested steps can be matched and steps setting install values always run so later steps
+// expand the same way
+pub fn is_step_selected(location: &StepLocation, command: &str, apply_only: bool) -> bool {
+    let filter = STEP_FILTER.lock().unwrap();
+
+    if filter
+        .skip
+        .iter()
+        .any(|pattern| matches(pattern, location, command))
+    {
+        return false;
+    }
+
+    if !apply_only || filter.only.is_empty() {
+        return true;
+    }
+
+    return filter
+        .only
+        .iter()
+        .any(|pattern| matches(pattern, location, command));
+}
@@ -0,0 +1,222 @@
+use super::commands;
+
+use commands::common::StepLocation;
+use commands::errors::InstallError;
+
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{from_value, Map, Value};
+use std::time::Duration;
+
+// keys that can be placed next to the command key of a step, they apply to any command
+pub const STEP_OPTION_KEYS: [&str; 5] = ["retry", "continue_on_error", "id", "needs", "locks"];
+
+/// Options accepted by every step next to its command.
+#[derive(Deserialize, Serialize, JsonSchema)]
+pub struct StepOptions {
+    /// Run the step again when it fails.
+    #[serde(default)]
+    pub retry: Option<RetryPolicy>,
+
+    /// Record a failure of the step and go on with the next one instead of stopping the run.
+    #[serde(default)]
+    pub continue_on_error: bool,
+
+    /// Name other steps use in needs, unique for the whole run. Steps of a call or of a foreach item only need unique ids within that run of the steps, and only the steps of that run can need them.
+    #[serde(default)]
+    pub id: Option<String>,
+
+    /// Ids of the steps that must succeed before this one starts, instead of the step placed before it. A block with needs runs every step as soon as what it needs is done, [] starts right away. The step is skipped when one of them fails.
+    #[serde(default)]
+    pub needs: Option<Vec<String>>,
+
+    /// Names of resources the step can't share, steps holding the same lock never run their command at the same time. winget and vcpkg steps always hold the lock named after them.
+    #[serde(default)]
+    pub locks: Vec<String>,
+}
+
+/// Retries a failing step, waiting longer after each failed attempt.
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    #[serde(default = "default_attempts")]
+    pub attempts: u32,
+
+    /// Seconds to wait before the first retry.
+    #[serde(default = "default_delay")]
+    pub delay: f64,
+
+    /// Multiplier applied to the delay after every retry, 1 keeps it constant. Waits never exceed an hour.
+    #[serde(default = "default_backoff")]
+    pub backoff: f64,
+
+    /// Exit codes worth retrying, any non zero code is retried if missing. Timeouts are always retried.
+    #[serde(default)]
+    pub exit_codes: Option<Vec<i32>>,
+}
+
+fn default_attempts() -> u32 {
+    return 3;
+}
+
+// longest wait between two attempts, in seconds
+const MAX_RETRY_DELAY_SECS: f64 = 3600.0;
+
+fn default_delay() -> f64 {
+    return 5.0;
+}
+
+fn default_backoff() -> f64 {
+    return 2.0;
+}
+
+impl RetryPolicy {
+    // attempt is the number of the attempt that just failed, starting from 1
+    pub fn should_retry(&self, err: &InstallError, attempt: u32) -> bool {
+        if attempt >= self.attempts {
+            return false;
+        }
+
+        match err {
+            InstallError::ExitCode { code, .. } => match (&self.exit_codes, code) {
+                (None, _) => return true,
+                (Some(exit_codes), Some(code)) => return exit_codes.contains(code),
+                (Some(_), None) => return false,
+            },
+            InstallError::Timeout { .. } => return true,
+            // invalid configs, cancellation and the like won't go away by running again
+            _ => return false,
+        }
+    }
+
+    pub fn delay_before_retry(&self, attempt: u32) -> Duration {
+        let seconds = self.delay * self.backoff.powi(attempt as i32 - 1);
+
+        // a large backoff overflows after enough attempts
+        if seconds.is_nan() || seconds > MAX_RETRY_DELAY_SECS {
+            return Duration::from_secs_f64(MAX_RETRY_DELAY_SECS);
+        }
+        return Duration::from_secs_f64(seconds.max(0.0));
+    }
+
+    // problems that would make the policy misbehave at runtime
+    pub fn check(&self) -> Option<String> {
+        if self.attempts == 0 {
+            return Some(String::from("retry attempts must be at least 1"));
+        }
+
+        if !self.delay.is_finite() || self.delay < 0.0 {
+            return Some(format!(
+                "retry delay must be a positive number, found {}",
+                self.delay
+            ));
+        }
+
+        if !self.backoff.is_finite() || self.backoff < 1.0 {
+            return Some(format!(
+                "retry backoff must be at least 1, found {}",
+                self.backoff
+            ));
+        }
+
+        return None;
+    }
+}
+
+// splits a step into its command name, command data and options,
+// ex: {"winget": {...}, "retry": {"attempts": 5}}
+pub fn split_step<'a>(
+    object: &'a Map<String, Value>,
+    location: &StepLocation,
+) -> Result<(&'a String, &'a Value, Map<String, Value>), InstallError> {
+    let mut command: Option<(&String, &Value)> = None;
+    let mut options = Map::new();
+
+    for (key, value) in object.iter() {
+        if STEP_OPTION_KEYS.contains(&key.as_str()) {
+            options.insert(key.clone(), value.clone());
+            continue;
+        }
+
+        if command.is_some() {
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: format!(
+                    "Invalid instruction found, expected exactly one command: {}",
+                    Value::Object(object.clone())
+                ),
+            });
+        }
+        command = Some((key, value));
+    }
+
+    match command {
+        Some((name, json_data)) => return Ok((name, json_data, options)),
+        None => {
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: format!(
+                    "Invalid instruction found, no command in: {}",
+                    Value::Object(object.clone())
+                ),
+            });
+        }
+    }
+}
+
+pub fn parse_step_options(
+    options: Map<String, Value>,
+    location: &StepLocation,
+) -> Result<StepOptions, InstallError> {
+    match from_value::<StepOptions>(Value::Object(options)) {
+        Ok(options) => {
+            if let Some(retry) = &options.retry {
+                if let Some(message) = retry.check() {
+                    return Err(InstallError::Parse {
+                        location: location.clone(),
+                        message,
+                    });
+                }
+            }
+
+            return Ok(options);
+        }
+        Err(err) => {
+            return Err(InstallError::Parse {
+                location: location.clone(),
+                message: format!("Failed to convert step options, err: {}", err),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(delay: f64, backoff: f64) -> RetryPolicy {
+        return RetryPolicy {
+            attempts: 100,
+            delay,
+            backoff,
+            exit_codes: None,
+        };
+    }
+
+    #[test]
+    fn delay_grows_with_backoff() {
+        let policy = policy(2.0, 3.0);
+        assert_eq!(policy.delay_before_retry(1), Duration::from_secs(2));
+        assert_eq!(policy.delay_before_retry(3), Duration::from_secs(18));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let max = Duration::from_secs_f64(MAX_RETRY_DELAY_SECS);
+        assert_eq!(policy(5.0, 2.0).delay_before_retry(20), max);
+        assert_eq!(policy(5.0, 1e300).delay_before_retry(99), max);
+        assert_eq!(policy(f64::MAX, f64::MAX).delay_before_retry(3), max);
+        assert_eq!(policy(f64::NAN, 2.0).delay_before_retry(1), max);
+    }
+}
@@ -0,0 +1,201 @@
+use super::commands;
+
+use commands::common::StepLocation;
+use commands::errors::InstallError;
+
+use chrono::{DateTime, Local};
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+pub enum StepOutcome {
+    Succeeded,
+    // message and exit code of the error that stopped the step
+    Failed(String, i32),
+    // not run, with the reason why, ex: completed by a previous run
+    Skipped(String),
+}
+
+pub struct StepRecord {
+    pub location: StepLocation,
+    pub command: String,
+    // step data with install values expanded
+    pub parameters: Value,
+    pub started: DateTime<Local>,
+    pub finished: DateTime<Local>,
+    // exit code of the process run by the step, known only when it failed because of it
+    pub exit_code: Option<i32>,
+    pub outcome: StepOutcome,
+    // order in which records were made, see record_mark
+    sequence: usize,
+}
+
+lazy_static! {
+    static ref STEP_RECORDS: Mutex<Vec<StepRecord>> = Mutex::new(Vec::new());
+}
+
+static NEXT_SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+// a step run again by a retry of its block replaces the record of the earlier attempt, every
+// location keeps only its final outcome
+fn push_record(mut record: StepRecord) {
+    record.sequence = NEXT_SEQUENCE.fetch_add(1, Ordering::SeqCst);
+
+    let mut records = STEP_RECORDS.lock().unwrap();
+    match records
+        .iter_mut()
+        .find(|earlier| earlier.location == record.location)
+    {
+        Some(earlier) => *earlier = record,
+        None => records.push(record),
+    }
+}
+
+// records made after this call are found by is_step_recorded_since
+pub fn record_mark() -> usize {
+    return NEXT_SEQUENCE.load(Ordering::SeqCst);
+}
+
+// started is when the step began running, the record is finished at the time of the call
+pub fn record_step(
+    location: &StepLocation,
+    command: &str,
+    parameters: Value,
+    started: DateTime<Local>,
+    outcome: StepOutcome,
+) {
+    push_record(StepRecord {
+        location: location.clone(),
+        command: command.to_string(),
+        parameters,
+        started,
+        finished: Local::now(),
+        exit_code: None,
+        outcome,
+        sequence: 0,
+    });
+}
+
+pub fn record_failure(
+    location: &StepLocation,
+    command: &str,
+    parameters: Value,
+    started: DateTime<Local>,
+    err: &InstallError,
+) {
+    let exit_code = match err {
+        InstallError::ExitCode { code, .. } => *code,
+        _ => None,
+    };
+
+    push_record(StepRecord {
+        location: location.clone(),
+        command: command.to_string(),
+        parameters,
+        started,
+        finished: Local::now(),
+        exit_code,
+        outcome: StepOutcome::Failed(err.to_string(), err.exit_code()),
+        sequence: 0,
+    });
+}
+
+pub fn step_records() -> MutexGuard<'static, Vec<StepRecord>> {
+    return STEP_RECORDS.lock().unwrap();
+}
+
+// recorded by the current attempt of the step, mark is taken before it started
+pub fn is_step_recorded_since(location: &StepLocation, mark: usize) -> bool {
+    return STEP_RECORDS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|record| &record.location == location && record.sequence >= mark);
+}
+
+pub fn is_step_failed(location: &StepLocation) -> bool {
+    return STEP_RECORDS.lock().unwrap().iter().any(|record| {
+        &record.location == location && matches!(record.outcome, StepOutcome::Failed(..))
+    });
+}
+
+pub fn failed_steps_count() -> usize {
+    return STEP_RECORDS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|record| matches!(record.outcome, StepOutcome::Failed(..)))
+        .count();
+}
+
+// exit code of the first step whose final outcome is a failure, used when the run went on after
+// failing steps
+pub fn first_failure_exit_code() -> Option<i32> {
+    for record in STEP_RECORDS.lock().unwrap().iter() {
+        if let StepOutcome::Failed(_, exit_code) = record.outcome {
+            return Some(exit_code);
+        }
+    }
+
+    return None;
+}
+
+pub fn print_summary() {
+    let records = STEP_RECORDS.lock().unwrap();
+    if records.is_empty() {
+        return;
+    }
+
+    let mut succeeded = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+
+    let command_width = records
+        .iter()
+        .map(|record| record.command.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut errors: Vec<&String> = Vec::new();
+
+    println!();
+    for record in records.iter() {
+        let status;
+        match &record.outcome {
+            StepOutcome::Succeeded => {
+                succeeded += 1;
+                status = "OK";
+            }
+            StepOutcome::Failed(err, _) => {
+                failed += 1;
+                status = "FAILED";
+                errors.push(err);
+            }
+            StepOutcome::Skipped(_) => {
+                skipped += 1;
+                status = "SKIPPED";
+            }
+        }
+
+        println!(
+            "{:<7}  {:<width$}  {}",
+            status,
+            record.command,
+            record.location,
+            width = command_width
+        );
+    }
+
+    println!(
+        "Summary: {} succeeded, {} failed, {} skipped",
+        succeeded, failed, skipped
+    );
+
+    if !errors.is_empty() {
+        println!("Failed steps:");
+        for err in errors {
+            println!("  {}", err);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use super::commands;
+
+use commands::common::StepLocation;
+use commands::errors::InstallError;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{from_value, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+use log::debug;
+
+use super::executor_factory::ExecutorFactory;
+use super::rendering::{block_steps, load_config_file};
+use super::scheduler::{find_cycle, BlockStep};
+use super::step_options::{split_step, StepOptions};
+
+// step met while validating, in the order a run would reach it
+pub struct ListedStep {
+    pub location: StepLocation,
+    pub command: String,
+    pub json_data: Value,
+    // false for blocks of steps and steps setting install values, see is_step_selected
+    pub does_work: bool,
+}
+
+// collects every problem found while statically checking a config and its includes,
+// nothing gets executed while validating
+pub struct Validator {
+    include_stack: Vec<String>,
+    validated_configs: HashSet<String>,
+    // step ids met so far by scope, needs can only name these, see BlockMode::scoped
+    ids: HashMap<(String, String), StepLocation>,
+    // scoped blocks being validated, innermost last
+    scopes: Vec<String>,
+    pub errors: Vec<InstallError>,
+    pub steps: Vec<ListedStep>,
+}
+
+impl Validator {
+    pub fn new() -> Validator {
+        return Validator {
+            include_stack: Vec::new(),
+            validated_configs: HashSet::new(),
+            ids: HashMap::new(),
+            scopes: Vec::new(),
+            errors: Vec::new(),
+            steps: Vec::new(),
+        };
+    }
+
+    pub fn report(&mut self, err: InstallError) {
+        self.errors.push(err);
+    }
+
+    pub fn validate_config(&mut self, conf_file: &String, location: &StepLocation) {
+        if self.include_stack.contains(conf_file) {
+            let mut cycle = self.include_stack.clone();
+            cycle.push(conf_file.clone());
+
+            self.report(InstallError::Include {
+                location: location.clone(),
+                config_path: conf_file.clone(),
+                message: format!("Include cycle detected: {}", cycle.join(" -> ")),
+            });
+            return;
+        }
+
+        if self.validated_configs.contains(conf_file) {
+            return;
+        }
+        self.validated_configs.insert(conf_file.clone());
+
+        match load_config_file(conf_file) {
+            Ok(json_data) => {
+                self.include_stack.push(conf_file.clone());
+                self.validate_block(&json_data, &StepLocation::new(conf_file));
+                self.include_stack.pop();
+            }
+            Err(err) => {
+                self.report(err);
+            }
+        }
+    }
+
+    // location identifies the block itself, same as for rendering::render
+    pub fn validate_block(&mut self, json_data: &Value, location: &StepLocation) {
+        self.validate_steps(json_data, location, false);
+    }
+
+    // block whose steps don't wait for the one placed before them, see rendering::render_block
+    pub fn validate_parallel_block(&mut self, json_data: &Value, location: &StepLocation) {
+        self.validate_steps(json_data, location, true);
+    }
+
+    // block whose ids are only known inside it, see rendering::render_scoped
+    pub fn validate_scoped_block(&mut self, json_data: &Value, location: &StepLocation) {
+        self.scopes.push(location.to_string());
+        self.validate_steps(json_data, location, false);
+        self.scopes.pop();
+    }
+
+    fn current_scope(&self) -> String {
+        return self.scopes.last().cloned().unwrap_or_default();
+    }
+
+    // ids of the enclosing scopes are visible too
+    fn is_known_id(&self, id: &str) -> bool {
+        return self
+            .scopes
+            .iter()
+            .cloned()
+            .chain(std::iter::once(String::new()))
+            .any(|scope| self.ids.contains_key(&(scope, id.to_string())));
+    }
+
+    // registers the ids of the block and reports needs that can never be satisfied
+    fn validate_ids(&mut self, steps: &[BlockStep], parallel: bool) {
+        for step in steps.iter() {
+            if let Some(id) = &step.id {
+                let scoped_id = (self.current_scope(), id.clone());
+                match self.ids.get(&scoped_id) {
+                    Some(other) if other != &step.location => {
+                        let message =
+                            format!("Duplicate step id \"{}\", already used by {}", id, other);
+                        self.report(InstallError::Dependency {
+                            location: step.location.clone(),
+                            message,
+                        });
+                    }
+                    _ => {
+                        self.ids.insert(scoped_id, step.location.clone());
+                    }
+                }
+            }
+        }
+
+        let mut edges: HashMap<String, Vec<String>> = HashMap::new();
+        for (idx, step) in steps.iter().enumerate() {
+            let needs: Vec<String> = match &step.needs {
+                Some(names) => steps
+                    .iter()
+                    .filter(|other| matches!(&other.id, Some(id) if names.contains(id)))
+                    .map(|other| other.location.to_string())
+                    .collect(),
+                None if !parallel && idx > 0 => vec![steps[idx - 1].location.to_string()],
+                None => vec![],
+            };
+            edges.insert(step.location.to_string(), needs);
+        }
+
+        for step in steps.iter() {
+            if let Some(cycle) = find_cycle(&edges, &step.location.to_string()) {
+                let labels: Vec<String> = cycle
+                    .iter()
+                    .map(|key| {
+                        match steps
+                            .iter()
+                            .find(|other| &other.location.to_string() == key)
+                        {
+                            Some(BlockStep { id: Some(id), .. }) => return id.clone(),
+                            _ => return key.clone(),
+                        }
+                    })
+                    .collect();
+
+                self.report(InstallError::Dependency {
+                    location: step.location.clone(),
+                    message: format!("Step dependency cycle: {}", labels.join(" -> ")),
+                });
+                return;
+            }
+        }
+    }
+
+    fn validate_steps(&mut self, json_data: &Value, location: &StepLocation, parallel: bool) {
+        if let Value::Array(obj) = json_data {
+            let steps: Vec<(StepLocation, Map<String, Value>)> = obj
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, value)| match value {
+                    Value::Object(object) => Some((location.child(idx), object.clone())),
+                    _ => None,
+                })
+                .collect();
+            let block_steps = block_steps(&steps);
+            self.validate_ids(&block_steps, parallel);
+
+            let mut block_steps = block_steps.iter();
+            for (idx, value) in obj.iter().enumerate() {
+                if let Value::Object(object) = value {
+                    // ids of steps nested in the ones placed before are known by now
+                    if let Some(BlockStep {
+                        needs: Some(names), ..
+                    }) = block_steps.next()
+                    {
+                        for name in names.iter() {
+                            if !self.is_known_id(name) {
+                                self.report(InstallError::Dependency {
+                                    location: location.child(idx),
+                                    message: format!(
+                                        "Unknown step id \"{}\", steps can only need steps of their block or placed before them",
+                                        name
+                                    ),
+                                });
+                            }
+                        }
+                    }
+
+                    self.validate_step(object, &location.child(idx));
+                } else {
+                    // plain strings are used as comments inside configs
+                    debug!(
+                        "{}: Ignoring entry that is not a command: {}",
+                        location.child(idx),
+                        value
+                    );
+                }
+            }
+        } else {
+            self.report(InstallError::Parse {
+                location: location.clone(),
+                message: format!(
+                    "Invalid syntax, comands are supposed to be contained into an array of objects, found {}",
+                    json_data
+                ),
+            });
+        }
+    }
+
+    fn validate_step(&mut self, object: &Map<String, Value>, location: &StepLocation) {
+        match split_step(object, location) {
+            Ok((name, json_data, options)) => {
+                if let Some(options) = validate_step_data::<StepOptions>(
+                    &Value::Object(options),
+                    "StepOptions",
+                    location,
+                    self,
+                ) {
+                    if let Some(message) = options.retry.as_ref().and_then(|retry| retry.check()) {
+                        self.report(InstallError::Parse {
+                            location: location.clone(),
+                            message,
+                        });
+                    }
+                }
+
+                match ExecutorFactory::build(name.as_str()) {
+                    Some(executor) => {
+                        self.steps.push(ListedStep {
+                            location: location.clone(),
+                            command: name.clone(),
+                            json_data: json_data.clone(),
+                            does_work: !executor.runs_nested_steps()
+                                && !executor.sets_install_values()
+                                && !executor.is_definition(),
+                        });
+                        executor.validate(json_data, location, self);
+                    }
+                    None => {
+                        self.report(InstallError::UnknownCommand {
+                            location: location.clone(),
+                            name: name.clone(),
+                        });
+                    }
+                }
+            }
+            Err(err) => {
+                self.report(err);
+            }
+        }
+    }
+}
+
+// checks that the step data deserializes into its command and that it has no fields the command
+// would silently ignore, returns the command so callers can validate nested data
+pub fn validate_step_data<T: DeserializeOwned + Serialize>(
+    json_data: &Value,
+    command_name: &str,
+    location: &StepLocation,
+    validator: &mut Validator,
+) -> Option<T> {
+    match from_value::<T>(json_data.clone()) {
+        Ok(cmd) => {
+            if let (Value::Object(given), Ok(Value::Object(known))) =
+                (json_data, serde_json::to_value(&cmd))
+            {
+                for field in given.keys() {
+                    if !known.contains_key(field) {
+                        validator.report(InstallError::Parse {
+                            location: location.clone(),
+                            message: format!(
+                                "Unknown field \"{}\" for {}, expected one of: {}",
+                                field,
+                                command_name,
+                                known.keys().cloned().collect::<Vec<String>>().join(", ")
+                            ),
+                        });
+                    }
+                }
+            }
+
+            return Some(cmd);
+        }
+        Err(err) => {
+            validator.report(InstallError::Parse {
+                location: location.clone(),
+                message: format!("Failed to convert data to {}, err: {}", command_name, err),
+            });
+            return None;
+        }
+    }
+}
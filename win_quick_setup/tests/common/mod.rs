@@ -0,0 +1,158 @@
+// every test file includes its own copy, not all of them use every helper
+#![allow(dead_code)]
+
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+// directory holding a copy of the configs in resources, removed once the test is done
+pub struct Sandbox {
+    pub dir: PathBuf,
+}
+
+// what a run of the binary did, locations are relative to the sandbox
+pub struct RunResult {
+    pub code: i32,
+    pub stdout: String,
+    pub invocations: Vec<Value>,
+}
+
+impl RunResult {
+    // (location, command line) of every program the fake was asked to start
+    pub fn commands(&self) -> Vec<(String, String)> {
+        return self
+            .invocations
+            .iter()
+            .map(|invocation| {
+                return (
+                    invocation["location"].as_str().unwrap().to_string(),
+                    invocation["command_line"].as_str().unwrap().to_string(),
+                );
+            })
+            .collect();
+    }
+
+    pub fn command_lines(&self) -> Vec<String> {
+        return self.commands().into_iter().map(|(_, line)| line).collect();
+    }
+}
+
+impl Sandbox {
+    pub fn new(name: &str) -> Sandbox {
+        let dir =
+            std::env::temp_dir().join(format!("win_quick_setup_{}_{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let resources = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources");
+        for entry in fs::read_dir(resources).unwrap() {
+            let path = entry.unwrap().path();
+            fs::copy(&path, dir.join(path.file_name().unwrap())).unwrap();
+        }
+
+        fs::write(dir.join("fake.json"), "[]").unwrap();
+        // the Run key every windows user has
+        fs::write(
+            dir.join("registry.json"),
+            r#"{"Software\\Microsoft\\Windows\\CurrentVersion\\Run": {}}"#,
+        )
+        .unwrap();
+        return Sandbox { dir };
+    }
+
+    pub fn path(&self, name: &str) -> String {
+        return self.dir.join(name).to_string_lossy().to_string();
+    }
+
+    pub fn write(&self, name: &str, contents: &str) {
+        fs::write(self.dir.join(name), contents).unwrap();
+    }
+
+    pub fn read(&self, name: &str) -> String {
+        return fs::read_to_string(self.dir.join(name)).unwrap();
+    }
+
+    // arguments run passes to the binary, fake processes and a registry file in the sandbox,
+    // PROGRAMS_DIR points inside the sandbox too
+    pub fn args(&self, args: &[&str]) -> Vec<String> {
+        let mut all_args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        all_args.extend(vec![
+            String::from("--fake-processes"),
+            self.path("fake.json"),
+            String::from("--record-processes"),
+            self.path("recorded.json"),
+            String::from("--registry"),
+            self.path("registry.json"),
+            String::from("--set"),
+            format!("PROGRAMS_DIR={}", self.path("programs/")),
+        ]);
+        return all_args;
+    }
+
+    // exit code of the validate command on the config
+    pub fn validate(&self, config: &str) -> i32 {
+        let status = Command::new(env!("CARGO_BIN_EXE_win_quick_setup"))
+            .current_dir(&self.dir)
+            .args(&["validate", config])
+            .output()
+            .unwrap()
+            .status;
+        return status.code().unwrap();
+    }
+
+    pub fn run(&self, args: &[&str]) -> RunResult {
+        let recorded = self.path("recorded.json");
+        let _ = fs::remove_file(&recorded);
+
+        // a hung run fails the test instead of hanging it
+        let stdout_path = self.dir.join("stdout.txt");
+        let mut child = Command::new(env!("CARGO_BIN_EXE_win_quick_setup"))
+            .current_dir(&self.dir)
+            .args(self.args(args))
+            .stdout(fs::File::create(&stdout_path).unwrap())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().unwrap() {
+                break status;
+            }
+            if started.elapsed() > RUN_TIMEOUT {
+                let _ = child.kill();
+                panic!("{:?} still running after {:?}", args, RUN_TIMEOUT);
+            }
+            thread::sleep(Duration::from_millis(20));
+        };
+
+        let mut invocations = Vec::new();
+        if let Ok(contents) = fs::read_to_string(&recorded) {
+            let prefix = format!("{}/", self.dir.to_string_lossy());
+            for mut invocation in serde_json::from_str::<Vec<Value>>(&contents).unwrap() {
+                let location = invocation["location"]
+                    .as_str()
+                    .unwrap()
+                    .replace(&prefix, "");
+                invocation["location"] = Value::String(location);
+                invocations.push(invocation);
+            }
+        }
+
+        return RunResult {
+            code: status.code().unwrap(),
+            stdout: fs::read_to_string(&stdout_path).unwrap(),
+            invocations,
+        };
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,34 @@
+// facts describe the machine, the ones that need a process go through --fake-processes too
+mod common;
+
+use common::Sandbox;
+
+const ADMIN_ONLY: &str = r#"[
+    {"if": {"condition": "%IS_ADMIN%", "run": [
+        {"exec": {"install_run": "elevated"}}
+    ]}}
+]"#;
+
+#[test]
+fn admin_check_uses_the_fake_processes() {
+    let sandbox = Sandbox::new("facts_admin");
+    sandbox.write("admin.json", ADMIN_ONLY);
+
+    let result = sandbox.run(&["install", "admin.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(result.command_lines(), vec!["net session", "elevated"]);
+}
+
+#[test]
+fn failed_admin_check_means_not_admin() {
+    let sandbox = Sandbox::new("facts_not_admin");
+    sandbox.write("admin.json", ADMIN_ONLY);
+    sandbox.write(
+        "fake.json",
+        r#"[{"match": "^net session$", "exit_code": 2}]"#,
+    );
+
+    let result = sandbox.run(&["install", "admin.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(result.command_lines(), vec!["net session"]);
+}
@@ -0,0 +1,73 @@
+// foreach runs its block once per item, one item after the other or all at the same time
+mod common;
+
+use common::Sandbox;
+
+#[test]
+fn parallel_items_all_finish_when_one_fails() {
+    let sandbox = Sandbox::new("foreach_parallel");
+    sandbox.write(
+        "loop.json",
+        r#"[
+            {"foreach": {"items": ["a", "b", "c"], "parallel": true, "run": [
+                {"exec": {"install_run": "first %ITEM%"}},
+                {"exec": {"install_run": "second %ITEM%"}}
+            ]}}
+        ]"#,
+    );
+    sandbox.write("fake.json", r#"[{"match": "^first a$", "exit_code": 1}]"#);
+
+    let result = sandbox.run(&["install", "loop.json"]);
+    assert_eq!(result.code, 5);
+
+    let mut lines = result.command_lines();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec!["first a", "first b", "first c", "second b", "second c"]
+    );
+    assert!(result.stdout.contains("Summary: 4 succeeded, 1 failed"));
+}
+
+#[test]
+fn loop_variable_is_gone_after_the_loop() {
+    let sandbox = Sandbox::new("foreach_var_removed");
+    sandbox.write(
+        "loop.json",
+        r#"[
+            {"foreach": {"items": [{"name": "a"}, {"name": "b"}], "var": "PKG", "run": [
+                {"exec": {"install_run": "install %PKG.name%"}}
+            ]}},
+            {"exec": {"install_run": "after %PKG.name%"}}
+        ]"#,
+    );
+
+    let result = sandbox.run(&["install", "loop.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec!["install a", "install b", "after %PKG.name%"]
+    );
+}
+
+#[test]
+fn loop_variable_gets_its_value_back() {
+    let sandbox = Sandbox::new("foreach_var_restored");
+    sandbox.write(
+        "loop.json",
+        r#"[
+            {"set_var": {"key": "ITEM", "value": "outer"}},
+            {"foreach": {"items": "a;b", "run": [
+                {"exec": {"install_run": "inner %ITEM%"}}
+            ]}},
+            {"exec": {"install_run": "after %ITEM%"}}
+        ]"#,
+    );
+
+    let result = sandbox.run(&["install", "loop.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec!["inner a", "inner b", "after outer"]
+    );
+}
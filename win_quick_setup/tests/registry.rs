@@ -0,0 +1,101 @@
+// VMs.json keeps the command line of the run in the Run key while Hyper-V gets enabled, the
+// restarted run finds it there and goes on without enabling Hyper-V again
+mod common;
+
+use common::Sandbox;
+use serde_json::{json, Value};
+use std::fs;
+
+const RUN_PATH: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+const HYPER_V: &str =
+    "\"Enable-WindowsOptionalFeature -Online -FeatureName Microsoft-Hyper-V -All\"";
+
+// %CMD% of a run of VMs.json, the command line windows restarts after a reboot
+fn resume_command(sandbox: &Sandbox, args: &[&str]) -> String {
+    let config = fs::canonicalize(sandbox.dir.join("VMs.json"))
+        .unwrap()
+        .to_string_lossy()
+        .to_string();
+
+    let mut words = vec![env!("CARGO_BIN_EXE_win_quick_setup").to_string()];
+    for arg in sandbox.args(args) {
+        if arg == "VMs.json" {
+            words.push(config.clone());
+        } else {
+            words.push(arg);
+        }
+    }
+    words.push(String::from("--resume"));
+
+    let quoted: Vec<String> = words.iter().map(|word| format!("\"{}\"", word)).collect();
+    return quoted.join(" ");
+}
+
+fn registry(sandbox: &Sandbox) -> Value {
+    return serde_json::from_str(&sandbox.read("registry.json")).unwrap();
+}
+
+#[test]
+fn first_run_enables_hyper_v_and_clears_the_run_key() {
+    let sandbox = Sandbox::new("vms_first_run");
+    let result = sandbox.run(&["install", "VMs.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.commands(),
+        vec![(String::from("VMs.json#1/run/1"), String::from(HYPER_V))]
+    );
+    assert_eq!(registry(&sandbox), json!({ RUN_PATH: {} }));
+}
+
+#[test]
+fn interrupted_run_leaves_the_run_key_set() {
+    let sandbox = Sandbox::new("vms_interrupted");
+    sandbox.write("fake.json", r#"[{"match": "Hyper-V", "exit_code": 1}]"#);
+    let result = sandbox.run(&["install", "VMs.json"]);
+
+    assert_eq!(result.code, 5);
+    assert_eq!(
+        registry(&sandbox),
+        json!({ RUN_PATH: {"QuickSetupResume": resume_command(&sandbox, &["install", "VMs.json"])} })
+    );
+}
+
+#[test]
+fn restarted_run_skips_hyper_v() {
+    let sandbox = Sandbox::new("vms_restarted");
+    let command = resume_command(&sandbox, &["install", "VMs.json"]);
+    sandbox.write(
+        "registry.json",
+        &json!({ RUN_PATH: {"QuickSetupResume": command} }).to_string(),
+    );
+    let result = sandbox.run(&["install", "VMs.json"]);
+
+    assert_eq!(result.code, 0);
+    assert!(result.invocations.is_empty());
+    assert_eq!(registry(&sandbox), json!({ RUN_PATH: {} }));
+}
+
+#[test]
+fn run_key_of_another_command_is_replaced() {
+    let sandbox = Sandbox::new("vms_other_command");
+    sandbox.write(
+        "registry.json",
+        &json!({ RUN_PATH: {"QuickSetupResume": "\"other.exe\" \"--resume\""} }).to_string(),
+    );
+    let result = sandbox.run(&["install", "VMs.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(result.command_lines(), vec![HYPER_V]);
+}
+
+#[test]
+fn missing_run_key_fails_the_run() {
+    let sandbox = Sandbox::new("vms_no_run_key");
+    sandbox.write("registry.json", "{}");
+    let result = sandbox.run(&["install", "VMs.json"]);
+
+    assert_eq!(result.code, 7);
+    assert!(result.invocations.is_empty());
+    assert_eq!(registry(&sandbox), json!({}));
+}
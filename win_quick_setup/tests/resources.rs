@@ -0,0 +1,341 @@
+// runs the configs in resources through the binary with --fake-processes, checking the programs
+// every executor would start on windows
+mod common;
+
+use common::Sandbox;
+use serde_json::Value;
+use std::fs;
+
+fn winget(package: &str) -> String {
+    return format!("winget install --accept-package-agreements {}", package);
+}
+
+#[test]
+fn general_installs_git_with_winget() {
+    let sandbox = Sandbox::new("general");
+    let result = sandbox.run(&["install", "General.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.commands(),
+        vec![(String::from("General.json#0"), winget("Git.Git"))]
+    );
+    assert_eq!(result.invocations[0]["program"], "cmd");
+    assert_eq!(result.invocations[0]["args"][0], "/C");
+    assert_eq!(result.invocations[0]["args"][1], winget("Git.Git"));
+}
+
+#[test]
+fn msvc_runs_every_included_config() {
+    let sandbox = Sandbox::new("msvc");
+    let result = sandbox.run(&["install", "MSVC_C++.json"]);
+
+    assert_eq!(result.code, 0);
+    let locations: Vec<String> = result.commands().into_iter().map(|(l, _)| l).collect();
+    assert_eq!(
+        locations,
+        vec![
+            "VMs.json#1/run/1",
+            "General.json#0",
+            "WIN_General.json#2/0/run/0",
+            "WIN_General.json#2/1/run/0",
+            "WIN_General.json#2/2/run/0",
+            "WIN_General.json#2/3/run/0",
+            "WIN_General.json#2/4/run/0",
+            "WIN_General.json#2/5/run/0",
+            "WIN_General.json#2/6/run/0",
+            "WIN_General.json#3",
+            "MSVC_C++.json#5/run/0",
+            "MSVC_C++.json#5/run/1",
+            "MSVC_C++.json#5/run/2",
+            "MSVC_C++.json#6",
+            "MSVC_C++.json#7",
+            "WIN_Editors.json#0",
+        ]
+    );
+
+    let lines = result.command_lines();
+    assert_eq!(lines[2], winget("BurntSushi.ripgrep.MSVC"));
+    assert_eq!(lines[8], winget("Brave.Brave"));
+    assert_eq!(
+        lines[10],
+        "git clone https://github.com/Microsoft/vcpkg.git vcpkg"
+    );
+    // fake processes exit with 0, so the guard finds vcpkg on PATH already
+    assert!(
+        lines[12].contains("-contains '")
+            && lines[12].ends_with("vcpkg') { exit 0 } else { exit 1 }")
+    );
+    assert!(!lines.iter().any(|line| line.starts_with("setx")));
+    assert_eq!(lines[14], "vcpkg install nlohmann-json");
+    assert_eq!(lines[15], winget("Microsoft.VisualStudio.2022.Community"));
+
+    // the dir step created PROGRAMS_DIR, ps1 steps with a dir run in it
+    assert!(sandbox.dir.join("programs").is_dir());
+    assert_eq!(
+        result.invocations[10]["dir"],
+        sandbox.path("programs/").as_str()
+    );
+    assert_eq!(result.invocations[10]["program"], "powershell");
+}
+
+#[test]
+fn msvc_adds_vcpkg_to_path_when_missing() {
+    let sandbox = Sandbox::new("msvc_path");
+    sandbox.write(
+        "fake.json",
+        r#"[{"match": "GetEnvironmentVariable", "exit_code": 1}]"#,
+    );
+    let result = sandbox.run(&["install", "MSVC_C++.json"]);
+
+    assert_eq!(result.code, 0);
+    let setx: Vec<(String, String)> = result
+        .commands()
+        .into_iter()
+        .filter(|(location, _)| location == "MSVC_C++.json#5/run/2")
+        .collect();
+    assert_eq!(setx.len(), 2);
+    assert!(setx[0]
+        .1
+        .contains("GetEnvironmentVariable('PATH', 'Machine')"));
+    assert!(setx[1].1.starts_with("setx PATH \""));
+    assert!(setx[1]
+        .1
+        .ends_with(&format!("{}vcpkg\" /M", sandbox.path("programs/"))));
+}
+
+#[test]
+fn ps1_passes_the_command_to_powershell() {
+    let sandbox = Sandbox::new("ps1");
+    let result = sandbox.run(&["install", "WebDev.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.commands(),
+        vec![(
+            String::from("WebDev.json#1"),
+            String::from("choco install protoc")
+        )]
+    );
+    assert_eq!(result.invocations[0]["program"], "powershell");
+    assert_eq!(result.invocations[0]["args"][0], "-Command");
+
+    let result = sandbox.run(&["uninstall", "WebDev.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(result.command_lines(), vec!["choco uninstall protoc"]);
+}
+
+#[test]
+fn parallel_block_starts_every_step() {
+    let sandbox = Sandbox::new("parallel");
+    let result = sandbox.run(&["install", "Websites_Tools.json"]);
+
+    assert_eq!(result.code, 0);
+    let mut lines = result.command_lines();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![winget("Docker.DockerDesktop "), winget("OpenJS.NodeJS")]
+    );
+}
+
+#[test]
+fn configs_run_one_after_the_other() {
+    let sandbox = Sandbox::new("several");
+    let result = sandbox.run(&["install", "Rust.json", "Python.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec![
+            winget("Rustlang.Rustup"),
+            winget("Microsoft.VisualStudioCode"),
+            winget("Python.Python.3.11"),
+            winget("Anaconda.Anaconda3"),
+        ]
+    );
+}
+
+#[test]
+fn empty_config_fails_to_parse() {
+    let sandbox = Sandbox::new("empty");
+    let result = sandbox.run(&["install", "Go.json"]);
+
+    assert_eq!(result.code, 3);
+    assert!(result.invocations.is_empty());
+}
+
+#[test]
+fn dry_run_starts_nothing() {
+    let sandbox = Sandbox::new("dry_run");
+    let report = sandbox.path("report.json");
+    let result = sandbox.run(&["install", "--dry-run", "--report", &report, "MSVC_C++.json"]);
+
+    assert_eq!(result.code, 0);
+    assert!(result.invocations.is_empty());
+    assert!(result.stdout.contains(&winget("Git.Git")));
+    assert!(result.stdout.contains("Summary: 0 succeeded, 0 failed"));
+
+    // planned steps aren't reported as done
+    let report: Value = serde_json::from_str(&sandbox.read("report.json")).unwrap();
+    for step in report["steps"].as_array().unwrap() {
+        assert_ne!(step["outcome"], "succeeded", "{}", step["path"]);
+    }
+    let git = report["steps"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|step| step["command"] == "winget")
+        .unwrap();
+    assert_eq!(git["outcome"], "skipped");
+    assert_eq!(git["skip_reason"], "dry run");
+}
+
+#[test]
+fn dry_run_skips_package_probes() {
+    let sandbox = Sandbox::new("dry_run_probe");
+    sandbox.write(
+        "probe.json",
+        r#"[{"if": {"condition": "!package_installed(winget, Git.Git)",
+                    "run": [{"include": {"config_path": "General.json"}}]}}]"#,
+    );
+
+    for args in [
+        vec!["install", "--dry-run", "probe.json"],
+        vec!["plan", "probe.json"],
+    ] {
+        let result = sandbox.run(&args);
+        assert_eq!(result.code, 0);
+        assert!(result.invocations.is_empty());
+        assert!(result.stdout.contains(
+            "[dry-run] package_installed(winget, Git.Git) would run \"winget list --exact --id Git.Git\""
+        ));
+        assert!(result.stdout.contains(&winget("Git.Git")));
+    }
+
+    let result = sandbox.run(&["install", "probe.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec!["winget list --exact --id Git.Git"]
+    );
+}
+
+#[test]
+fn failing_winget_stops_the_run() {
+    let sandbox = Sandbox::new("winget_fails");
+    sandbox.write(
+        "fake.json",
+        r#"[{"match": "Python\\.Python", "exit_code": 1}]"#,
+    );
+    let result = sandbox.run(&["install", "Python.json"]);
+
+    assert_eq!(result.code, 5);
+    assert_eq!(
+        result.command_lines(),
+        vec![
+            winget("Microsoft.VisualStudioCode"),
+            winget("Python.Python.3.11"),
+        ]
+    );
+}
+
+#[test]
+fn already_installed_winget_package_succeeds() {
+    let sandbox = Sandbox::new("winget_installed");
+    sandbox.write(
+        "fake.json",
+        r#"[{"match": "Git\\.Git", "exit_code": -1978335135}]"#,
+    );
+    let result = sandbox.run(&["install", "General.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(result.command_lines(), vec![winget("Git.Git")]);
+}
+
+#[test]
+fn failing_winget_is_retried() {
+    let sandbox = Sandbox::new("winget_retry");
+    sandbox.write("fake.json", r#"[{"match": "Git\\.Git", "exit_code": 1}]"#);
+    sandbox.write(
+        "retry.json",
+        r#"[{"winget": {"package": "Git.Git"}, "retry": {"attempts": 2, "delay": 0}}]"#,
+    );
+    let result = sandbox.run(&["install", "retry.json"]);
+
+    assert_eq!(result.code, 5);
+    assert_eq!(
+        result.command_lines(),
+        vec![winget("Git.Git"), winget("Git.Git")]
+    );
+}
+
+#[test]
+fn missing_program_fails_to_spawn() {
+    let sandbox = Sandbox::new("not_found");
+    sandbox.write("fake.json", r#"[{"match": "^choco", "not_found": true}]"#);
+    let result = sandbox.run(&["install", "WebDev.json"]);
+
+    assert_eq!(result.code, 6);
+    assert_eq!(result.command_lines(), vec!["choco install protoc"]);
+}
+
+#[test]
+fn creates_guard_skips_what_exists() {
+    let sandbox = Sandbox::new("creates");
+    fs::create_dir_all(sandbox.dir.join("programs/vcpkg")).unwrap();
+    let result = sandbox.run(&["install", "MSVC_C++.json"]);
+
+    assert_eq!(result.code, 0);
+    let lines = result.command_lines();
+    assert!(!lines.iter().any(|line| line.starts_with("git clone")));
+    assert!(lines
+        .iter()
+        .any(|line| line.contains("bootstrap-vcpkg.bat")));
+}
+
+#[test]
+fn captured_output_is_used_by_later_steps() {
+    let sandbox = Sandbox::new("capture");
+    sandbox.write(
+        "fake.json",
+        r#"[{"match": "^git --version", "stdout": "git version 2.40.1\n"}]"#,
+    );
+    sandbox.write(
+        "capture.json",
+        r#"[
+            {"exec": {"install_run": "git --version",
+                      "capture_stdout": {"key": "GIT", "regex": "(\\d+\\.\\d+)"}}},
+            {"if": {"condition": "%GIT% >= 2.10",
+                    "run": [{"exec": {"install_run": "echo new git %GIT%"}}]}}
+        ]"#,
+    );
+    let result = sandbox.run(&["install", "capture.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec!["git --version", "echo new git 2.40"]
+    );
+}
+
+#[test]
+fn config_included_under_another_spelling_runs_once() {
+    let sandbox = Sandbox::new("include_spelling");
+    sandbox.write(
+        "twice.json",
+        r#"[{"include": {"config_path": "Python.json"}},
+            {"include": {"config_path": "./Python.json"}}]"#,
+    );
+    let result = sandbox.run(&["install", "twice.json"]);
+
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec![
+            winget("Microsoft.VisualStudioCode"),
+            winget("Python.Python.3.11"),
+            winget("Anaconda.Anaconda3"),
+        ]
+    );
+}
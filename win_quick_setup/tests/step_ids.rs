@@ -0,0 +1,119 @@
+// ids of steps in define templates and foreach blocks only have to be unique for one run of the
+// block, steps outside can't need them
+mod common;
+
+use common::Sandbox;
+
+const TEMPLATE: &str = r#"[
+    {"exec": {"install_run": "setup"}, "id": "setup"},
+    {"define": {"name": "tool", "params": {"N": null}, "run": [
+        {"exec": {"install_run": "fetch %N%"}, "id": "fetch", "needs": ["setup"]},
+        {"exec": {"install_run": "build %N%"}, "needs": ["fetch"]}
+    ]}},
+    {"call": {"name": "tool", "args": {"N": "a"}}},
+    {"call": {"name": "tool", "args": {"N": "b"}}},
+    {"foreach": {"items": ["x", "y"], "var": "I", "run": [
+        {"exec": {"install_run": "loop %I%"}, "id": "loop"},
+        {"exec": {"install_run": "after %I%"}, "needs": ["loop"]}
+    ]}}
+]"#;
+
+#[test]
+fn ids_repeat_across_calls_and_items() {
+    let sandbox = Sandbox::new("ids_scoped");
+    sandbox.write("ids.json", TEMPLATE);
+
+    assert_eq!(sandbox.validate("ids.json"), 0);
+
+    let result = sandbox.run(&["install", "ids.json"]);
+    assert_eq!(result.code, 0);
+    assert_eq!(
+        result.command_lines(),
+        vec![
+            "setup", "fetch a", "build a", "fetch b", "build b", "loop x", "after x", "loop y",
+            "after y",
+        ]
+    );
+}
+
+#[test]
+fn failed_need_only_skips_its_own_call() {
+    let sandbox = Sandbox::new("ids_failed_need");
+    sandbox.write("ids.json", TEMPLATE);
+    sandbox.write("fake.json", r#"[{"match": "^fetch a$", "exit_code": 1}]"#);
+
+    let result = sandbox.run(&["install", "--keep-going", "ids.json"]);
+    assert_eq!(result.code, 5);
+    let lines = result.command_lines();
+    assert!(!lines.contains(&String::from("build a")));
+    assert!(lines.contains(&String::from("build b")));
+}
+
+#[test]
+fn need_of_a_step_left_behind_by_a_failed_block_is_skipped() {
+    let steps = r#"
+        {"if": {"condition": "true", "run": [
+            {"exec": {"install_run": "failme"}},
+            {"exec": {"install_run": "x"}, "id": "x"}
+        ]}},
+        {"exec": {"install_run": "y"}, "needs": ["x"]}
+    "#;
+    let configs = [
+        format!("[{}]", steps),
+        format!(
+            r#"[{{"paralel": {{"fail_fast": true, "run": [{}]}}}}]"#,
+            steps
+        ),
+        format!(
+            r#"[{{"paralel": {{"fail_fast": false, "run": [{}]}}}}]"#,
+            steps
+        ),
+    ];
+
+    for config in configs.iter() {
+        let sandbox = Sandbox::new("ids_block_failed");
+        sandbox.write("ids.json", config);
+        sandbox.write("fake.json", r#"[{"match": "^failme", "exit_code": 1}]"#);
+
+        let result = sandbox.run(&["install", "ids.json"]);
+        assert_eq!(result.code, 5, "{}", config);
+        assert_eq!(result.command_lines(), vec!["failme"], "{}", config);
+    }
+}
+
+#[test]
+fn ids_stay_unique_in_the_same_scope() {
+    let sandbox = Sandbox::new("ids_duplicate");
+    sandbox.write(
+        "ids.json",
+        r#"[
+            {"exec": {"install_run": "one"}, "id": "same"},
+            {"if": {"condition": "true", "run": [
+                {"exec": {"install_run": "two"}, "id": "same"}
+            ]}}
+        ]"#,
+    );
+
+    assert_eq!(sandbox.validate("ids.json"), 16);
+    let result = sandbox.run(&["install", "ids.json"]);
+    assert_eq!(result.code, 16);
+}
+
+#[test]
+fn scoped_ids_are_not_visible_outside() {
+    let sandbox = Sandbox::new("ids_outside");
+    sandbox.write(
+        "ids.json",
+        r#"[
+            {"foreach": {"items": ["x"], "run": [
+                {"exec": {"install_run": "loop"}, "id": "loop"}
+            ]}},
+            {"exec": {"install_run": "after"}, "needs": ["loop"]}
+        ]"#,
+    );
+
+    assert_eq!(sandbox.validate("ids.json"), 16);
+    let result = sandbox.run(&["install", "ids.json"]);
+    assert_eq!(result.code, 16);
+    assert_eq!(result.command_lines(), vec!["loop"]);
+}